@@ -0,0 +1,155 @@
+use std::f32::consts::PI;
+use std::io;
+
+/// Receives the sound produced by the machine, see [`Chip8::set_audio_sink`].
+///
+/// [`Chip8::set_audio_sink`]: crate::Chip8::set_audio_sink
+pub trait AudioSink {
+    /// The rate the samples are generated at, in Hz.
+    fn sample_rate(&self) -> u32;
+
+    /// Receives the next samples, mono, between -1.0 and 1.0. The machine sends one
+    /// frame, 1/60 s of sound, at a time.
+    fn write(&mut self, samples: &[f32]);
+
+    /// Makes sure everything written so far reached its destination, and reports the
+    /// errors `write` couldn't.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The shape of the buzzer tone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    /// The value of the wave at `phase`, between 0.0 and 1.0.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+/// The sound of the buzzer, played while the sound timer is not 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    /// Pitch in Hz.
+    pub frequency: f32,
+    /// Between 0.0 (mute) and 1.0.
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+/// What the machine plays during a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Silent,
+    /// The buzzer, a [`Tone`].
+    Buzzer,
+    /// An XO-CHIP 1-bit pattern, 128 samples played at `4000 * 2^((pitch - 64) / 48)` Hz.
+    Pattern {
+        pattern: [u8; 16],
+        pitch: u8,
+    },
+}
+
+/// Turns the [`Sound`] of each frame into samples.
+#[derive(Clone, Debug)]
+pub struct Synth {
+    tone: Tone,
+    sample_rate: u32,
+    /// Position in the current period of the tone, or in the 128 bits of the pattern.
+    phase: f32,
+    /// Samples that didn't fit in the previous frames, when the sample rate isn't a
+    /// multiple of 60.
+    leftover: u32,
+}
+
+impl Synth {
+    pub fn new(tone: Tone, sample_rate: u32) -> Synth {
+        Synth {
+            tone,
+            sample_rate,
+            phase: 0.0,
+            leftover: 0,
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    /// Appends one frame of `sound` at `frame_rate` frames per second to `out`.
+    pub fn render_frame(&mut self, sound: Sound, frame_rate: u32, out: &mut Vec<f32>) {
+        let total = self.sample_rate + self.leftover;
+        let len = total / frame_rate;
+        self.leftover = total % frame_rate;
+
+        out.reserve(len as usize);
+        for _ in 0..len {
+            let sample = self.next_sample(sound);
+            out.push(sample);
+        }
+    }
+
+    fn next_sample(&mut self, sound: Sound) -> f32 {
+        let volume = self.tone.volume.clamp(0.0, 1.0);
+
+        match sound {
+            Sound::Silent => {
+                self.phase = 0.0;
+                0.0
+            }
+            Sound::Buzzer => {
+                let sample = self.tone.waveform.sample(self.phase);
+                self.phase = (self.phase + self.tone.frequency / self.sample_rate as f32).fract();
+
+                sample * volume
+            }
+            Sound::Pattern { pattern, pitch } => {
+                let bit = self.phase as usize;
+                let on = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+
+                let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+                self.phase = (self.phase + rate / self.sample_rate as f32) % 128.0;
+
+                if on {
+                    volume
+                } else {
+                    -volume
+                }
+            }
+        }
+    }
+}
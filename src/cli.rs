@@ -0,0 +1,540 @@
+use chip8::{
+    Breakpoint, Config, Expr, Palette, Quirks, RngKind, Template, Variant, WatchAction, WatchKind,
+    Watchpoint, DEFAULT_PALETTE,
+};
+use std::fmt;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: chip-8 [COMMAND] ROM [OPTIONS]
+
+Commands:
+  run        Play the ROM in a window (the default)
+  disasm     Print the disassembly of the ROM
+  info       Print what is known about the ROM
+  headless   Run the ROM without a window
+  help       Print this message
+
+Options:
+  --variant <chip8|schip|xochip>  Instruction set and memory size [default: chip8]
+  --quirks <vip|chip48|schip|xochip>
+                                  Quirks profile [default: the one of the variant]
+  --quirk <NAME>=<on|off>         Overrides a single quirk, can be repeated
+  --ipf <N>                       Instructions per frame [default: 10]
+  --scale <1|2|4|8|16|32>         Size of a SUPER-CHIP pixel in the window [default: 8]
+  --colors <RRGGBB,...>           Up to 4 colors: off, plane 1, plane 2, both planes
+  --keymap <PRESET|FILE>          qwerty, azerty, qwertz, dvorak or a keymap file
+  --seed <N>                      Seed of the random number generator
+  --rng <xorshift|vip>            Random number generator [default: xorshift]
+  --paused                        Start paused, F12 resumes
+  --break <ADDR [after N] [if COND]>
+                                  Pause before the instruction at ADDR (hex), after
+                                  N hits, when COND is true, can be repeated
+  --trace <ADDR [...]: MESSAGE>   Print MESSAGE instead of pausing, see below
+  --watch <ADDR[-END][:r|w|c|rw]> Pause after an instruction reads (r), writes (w),
+                                  changes (c) or accesses (rw) memory [default: w]
+  --log-watch <ADDR[-END][:...]>  Print the accesses instead of pausing
+  --rewind <SECONDS>              How far back Backspace rewinds, 0 turns it off
+                                  [default: 60]
+  --record <FILE>                 Record a .gif, .y4m or raw RGB video from the start
+  --screenshots <DIR>             Where F10 saves screenshots and F11 recordings [default: .]
+  --screenshot-scale <N>          Image pixels per screen pixel, also for `--png` and
+                                  recordings [default: 1]
+  --frames <N>                    Frames to run headless [default: 600]
+  --input <FILE>                  Key presses to replay headless, see below
+  --png <FILE>                    Save the last screen of `headless` as a PNG
+  --pbm <FILE>                    Save the last screen of `headless` as a PBM
+  --ascii                         Print the last screen of `headless` as text
+  -h, --help                      Print this message
+  -V, --version                   Print the version
+
+`headless` prints the registers once done, and fails if the ROM crashes. An input
+script has one `FRAME press|release|tap KEYS...` per line, like `30 tap 5`.
+
+In the window F12 pauses. While paused Space steps one instruction, Enter steps over
+a CALL and Shift+Enter steps out of the subroutine.
+
+Conditions are C expressions on V0-VF, I, pc, dt, st, sp (the stack depth) and
+mem[ADDR], like `V3 == 0x10 && mem[I] != 0`. Messages print the values between
+braces, in hex with `:x`: `--trace '2A0 if V0 > 4: x={V0} at {I:x}'`.
+";
+
+/// A screenshot of the high resolution screen is 4096x2048 at this scale.
+const MAX_SCREENSHOT_SCALE: usize = 32;
+
+/// What to do with the ROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Run,
+    Disasm,
+    Info,
+    Headless,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Rom(Mode, Box<Options>),
+    Help,
+    Version,
+}
+
+/// The options of the commands that work on a ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub variant: Variant,
+    pub quirks: Option<Quirks>,
+    pub quirk_overrides: Vec<(String, bool)>,
+    pub instructions_per_frame: Option<usize>,
+    pub scale: usize,
+    pub palette: Palette,
+    pub keymap: Option<String>,
+    pub seed: Option<u64>,
+    pub rng: RngKind,
+    pub paused: bool,
+    pub breakpoints: Vec<(usize, Breakpoint)>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub rewind: usize,
+    pub record: Option<PathBuf>,
+    pub screenshots: PathBuf,
+    pub screenshot_scale: usize,
+    pub frames: usize,
+    pub input: Option<PathBuf>,
+    pub png: Option<PathBuf>,
+    pub pbm: Option<PathBuf>,
+    pub ascii: bool,
+}
+
+impl Options {
+    fn new(rom: PathBuf) -> Options {
+        Options {
+            rom,
+            variant: Variant::default(),
+            quirks: None,
+            quirk_overrides: Vec::new(),
+            instructions_per_frame: None,
+            scale: 8,
+            palette: DEFAULT_PALETTE,
+            keymap: None,
+            seed: None,
+            rng: RngKind::default(),
+            paused: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            rewind: 60,
+            record: None,
+            screenshots: PathBuf::from("."),
+            screenshot_scale: 1,
+            frames: 600,
+            input: None,
+            png: None,
+            pbm: None,
+            ascii: false,
+        }
+    }
+
+    /// The machine options, the variant first, then the quirks and their overrides.
+    pub fn config(&self) -> Config {
+        let mut config = Config::for_variant(self.variant);
+
+        if let Some(quirks) = self.quirks {
+            config.quirks = quirks;
+        }
+        for (name, enabled) in &self.quirk_overrides {
+            config.quirks.set(name, *enabled);
+        }
+        if let Some(ipf) = self.instructions_per_frame {
+            config.instructions_per_frame = ipf;
+        }
+
+        config.palette = self.palette;
+        config.seed = self.seed;
+        config.rng = self.rng;
+
+        config
+    }
+}
+
+/// A mistake in the command line, the message says what's wrong.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn error<T>(message: String) -> Result<T, UsageError> {
+    Err(UsageError(message))
+}
+
+/// Parses the arguments, without the program name.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, UsageError> {
+    let mut args = args.into_iter().peekable();
+
+    let mode = match args.peek().map(String::as_str) {
+        None | Some("help") | Some("-h") | Some("--help") => return Ok(Command::Help),
+        Some("-V") | Some("--version") => return Ok(Command::Version),
+        Some("run") => Some(Mode::Run),
+        Some("disasm") => Some(Mode::Disasm),
+        Some("info") => Some(Mode::Info),
+        Some("headless") => Some(Mode::Headless),
+        Some(_) => None,
+    };
+    if mode.is_some() {
+        args.next();
+    }
+
+    let mut rom = None;
+    let mut options = Options::new(PathBuf::new());
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if rom.is_some() {
+                return error(format!(
+                    "unexpected argument `{}`, only one ROM can be given",
+                    arg
+                ));
+            }
+            rom = Some(PathBuf::from(arg));
+            continue;
+        }
+
+        // Both `--flag value` and `--flag=value` work.
+        let (flag, inline) = match arg.find('=') {
+            Some(pos) => (arg[..pos].to_string(), Some(arg[pos + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || match inline.clone().or_else(|| args.next()) {
+            Some(value) => Ok(value),
+            None => error(format!("`{}` needs a value", flag)),
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--variant" => options.variant = parse_variant(&value()?)?,
+            "--quirks" => {
+                let name = value()?;
+                match Quirks::preset(&name) {
+                    Some(quirks) => options.quirks = Some(quirks),
+                    None => return error(format!("unknown quirks profile `{}`", name)),
+                }
+            }
+            "--quirk" => options.quirk_overrides.push(parse_quirk(&value()?)?),
+            "--ipf" => {
+                let ipf = parse_number(&flag, &value()?)?;
+                if ipf == 0 {
+                    return error("at least 1 instruction per frame is needed".to_string());
+                }
+                options.instructions_per_frame = Some(ipf);
+            }
+            "--scale" => {
+                let scale = parse_number(&flag, &value()?)?;
+                if ![1, 2, 4, 8, 16, 32].contains(&scale) {
+                    return error(format!(
+                        "the scale must be 1, 2, 4, 8, 16 or 32, not {}",
+                        scale
+                    ));
+                }
+                options.scale = scale;
+            }
+            "--colors" | "--colours" => options.palette = parse_palette(&value()?)?,
+            "--keymap" => options.keymap = Some(value()?),
+            "--seed" => options.seed = Some(parse_number(&flag, &value()?)?),
+            "--rng" => {
+                let name = value()?;
+                match RngKind::from_name(&name) {
+                    Some(kind) => options.rng = kind,
+                    None => return error(format!("unknown random number generator `{}`", name)),
+                }
+            }
+            "--paused" => options.paused = true,
+            "--break" => options.breakpoints.push(parse_breakpoint(&value()?)?),
+            "--trace" => options.breakpoints.push(parse_tracepoint(&value()?)?),
+            "--watch" => {
+                let watchpoint = parse_watchpoint(&value()?, WatchAction::Break)?;
+                options.watchpoints.push(watchpoint);
+            }
+            "--log-watch" => {
+                let watchpoint = parse_watchpoint(&value()?, WatchAction::Log)?;
+                options.watchpoints.push(watchpoint);
+            }
+            "--rewind" => options.rewind = parse_number(&flag, &value()?)?,
+            "--record" => options.record = Some(PathBuf::from(value()?)),
+            "--screenshots" => options.screenshots = PathBuf::from(value()?),
+            "--screenshot-scale" => {
+                let scale = parse_number(&flag, &value()?)?;
+                if !(1..=MAX_SCREENSHOT_SCALE).contains(&scale) {
+                    return error(format!(
+                        "the screenshot scale must be between 1 and {}, not {}",
+                        MAX_SCREENSHOT_SCALE, scale
+                    ));
+                }
+                options.screenshot_scale = scale;
+            }
+            "--frames" => options.frames = parse_number(&flag, &value()?)?,
+            "--input" => options.input = Some(PathBuf::from(value()?)),
+            "--png" => options.png = Some(PathBuf::from(value()?)),
+            "--pbm" => options.pbm = Some(PathBuf::from(value()?)),
+            "--ascii" => options.ascii = true,
+            _ => return error(format!("unknown option `{}`", flag)),
+        }
+    }
+
+    options.rom = match rom {
+        Some(rom) => rom,
+        None => return error("no ROM given".to_string()),
+    };
+
+    Ok(Command::Rom(mode.unwrap_or(Mode::Run), Box::new(options)))
+}
+
+fn parse_variant(name: &str) -> Result<Variant, UsageError> {
+    match name.to_lowercase().as_str() {
+        "chip8" | "chip-8" => Ok(Variant::Chip8),
+        "schip" | "superchip" | "super-chip" => Ok(Variant::SuperChip),
+        "xochip" | "xo-chip" => Ok(Variant::XoChip),
+        _ => error(format!("unknown variant `{}`", name)),
+    }
+}
+
+/// `name=on`, `name=off`, or just `name` to turn it on.
+fn parse_quirk(text: &str) -> Result<(String, bool), UsageError> {
+    let (name, enabled) = match text.find('=') {
+        Some(pos) => (&text[..pos], &text[pos + 1..]),
+        None => (text, "on"),
+    };
+
+    let enabled = match enabled {
+        "on" | "true" | "1" => true,
+        "off" | "false" | "0" => false,
+        _ => return error(format!("quirk `{}` must be `on` or `off`", name)),
+    };
+
+    if !Quirks::NAMES.contains(&name) {
+        let names = Quirks::NAMES.join(", ");
+        return error(format!(
+            "unknown quirk `{}`, the quirks are: {}",
+            name, names
+        ));
+    }
+
+    Ok((name.to_string(), enabled))
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, text: &str) -> Result<T, UsageError> {
+    match text.parse() {
+        Ok(n) => Ok(n),
+        Err(_) => error(format!("`{}` expects a number, not `{}`", flag, text)),
+    }
+}
+
+/// A hex address, with or without `0x` or `$`.
+fn parse_address(text: &str) -> Result<usize, UsageError> {
+    let hex = text
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+
+    match usize::from_str_radix(hex, 16) {
+        Ok(addr) if addr <= 0xffff => Ok(addr),
+        _ => error(format!("`{}` is not an address", text)),
+    }
+}
+
+/// `ADDR`, `ADDR-END`, optionally followed by `:r`, `:w`, `:c` or `:rw`.
+fn parse_watchpoint(text: &str, action: WatchAction) -> Result<Watchpoint, UsageError> {
+    let (addrs, kind) = match text.rsplit_once(':') {
+        Some((addrs, kind)) => (addrs, kind),
+        None => (text, "w"),
+    };
+
+    let kind = match kind {
+        "r" => WatchKind::Read,
+        "w" => WatchKind::Write,
+        "c" => WatchKind::Change,
+        "rw" => WatchKind::Access,
+        _ => {
+            return error(format!(
+                "watchpoint `{}` must end with `:r`, `:w`, `:c` or `:rw`",
+                text
+            ))
+        }
+    };
+
+    let (start, end) = match addrs.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => {
+            let addr = parse_address(addrs)?;
+            (addr, addr)
+        }
+    };
+    if start > end {
+        return error(format!("watchpoint `{}` ends before it starts", text));
+    }
+
+    Ok(Watchpoint::new(start..=end, kind, action))
+}
+
+/// `ADDR`, optionally followed by `after N` and `if COND`.
+fn parse_breakpoint(text: &str) -> Result<(usize, Breakpoint), UsageError> {
+    let mut breakpoint = Breakpoint::new();
+
+    let (head, condition) = match text.split_once(" if ") {
+        Some((head, condition)) => (head, Some(condition)),
+        None => (text, None),
+    };
+    if let Some(condition) = condition {
+        match Expr::parse(condition) {
+            Ok(expr) => breakpoint.condition = Some(expr),
+            Err(e) => return error(format!("condition `{}`: {}", condition, e)),
+        }
+    }
+
+    let words: Vec<&str> = head.split_whitespace().collect();
+    let addr = match words.as_slice() {
+        [addr] => parse_address(addr)?,
+        [addr, "after", hits] => {
+            breakpoint.ignore = parse_number("after", hits)?;
+            parse_address(addr)?
+        }
+        _ => {
+            return error(format!(
+                "breakpoint `{}` must be `ADDR [after N] [if COND]`",
+                text
+            ))
+        }
+    };
+
+    Ok((addr, breakpoint))
+}
+
+/// A breakpoint, then `:` and the message.
+fn parse_tracepoint(text: &str) -> Result<(usize, Breakpoint), UsageError> {
+    let (spec, message) = match text.split_once(':') {
+        Some((spec, message)) => (spec, message.trim_start()),
+        None => return error(format!("tracepoint `{}` has no `: MESSAGE`", text)),
+    };
+
+    let (addr, mut breakpoint) = parse_breakpoint(spec.trim())?;
+    match Template::parse(message) {
+        Ok(template) => breakpoint.message = Some(template),
+        Err(e) => return error(format!("message `{}`: {}", message, e)),
+    }
+
+    Ok((addr, breakpoint))
+}
+
+/// Comma separated `RRGGBB` colors, a leading `#` is allowed. The colors that aren't
+/// given keep their default.
+fn parse_palette(text: &str) -> Result<Palette, UsageError> {
+    let mut palette = DEFAULT_PALETTE;
+    let colors: Vec<&str> = text.split(',').map(str::trim).collect();
+
+    if colors.len() > palette.len() {
+        return error(format!("at most {} colors can be given", palette.len()));
+    }
+
+    for (slot, color) in palette.iter_mut().zip(colors) {
+        let hex = color.trim_start_matches('#');
+        *slot = match u32::from_str_radix(hex, 16) {
+            Ok(value) if hex.len() == 6 => value,
+            _ => return error(format!("`{}` is not a RRGGBB color", color)),
+        };
+    }
+
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Command, UsageError> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    fn options(line: &str) -> (Mode, Options) {
+        match parse_line(line) {
+            Ok(Command::Rom(mode, options)) => (mode, *options),
+            other => panic!("`{}` gave {:?}", line, other),
+        }
+    }
+
+    fn usage_error(line: &str) -> String {
+        parse_line(line).unwrap_err().0
+    }
+
+    #[test]
+    fn subcommands() {
+        for line in ["", "help", "-h", "--help", "run game.ch8 --help"] {
+            assert_eq!(parse_line(line), Ok(Command::Help), "`{}`", line);
+        }
+        assert_eq!(parse_line("-V"), Ok(Command::Version));
+        assert_eq!(parse_line("--version"), Ok(Command::Version));
+
+        assert_eq!(options("game.ch8").0, Mode::Run);
+        assert_eq!(options("run game.ch8").0, Mode::Run);
+        assert_eq!(options("disasm game.ch8").0, Mode::Disasm);
+        assert_eq!(options("info game.ch8").0, Mode::Info);
+        assert_eq!(options("headless game.ch8").0, Mode::Headless);
+
+        let (_, options) = options("game.ch8");
+        assert_eq!(options, Options::new(PathBuf::from("game.ch8")));
+    }
+
+    #[test]
+    fn values_follow_or_are_attached_to_their_flag() {
+        let (mode, options) = options(
+            "headless --ipf=20 --seed 7 --variant schip game.ch8 --frames 10 --ascii --rewind=0",
+        );
+
+        assert_eq!(mode, Mode::Headless);
+        assert_eq!(options.rom, PathBuf::from("game.ch8"));
+        assert_eq!(options.instructions_per_frame, Some(20));
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.variant, Variant::SuperChip);
+        assert_eq!(options.frames, 10);
+        assert!(options.ascii);
+        assert_eq!(options.rewind, 0);
+        assert_eq!(options.config().instructions_per_frame, 20);
+    }
+
+    #[test]
+    fn rejects_zero_instructions_per_frame() {
+        assert_eq!(
+            usage_error("game.ch8 --ipf 0"),
+            "at least 1 instruction per frame is needed"
+        );
+        assert_eq!(
+            usage_error("game.ch8 --ipf fast"),
+            "`--ipf` expects a number, not `fast`"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert_eq!(usage_error("game.ch8 --turbo"), "unknown option `--turbo`");
+        assert_eq!(
+            usage_error("--turbo=on game.ch8"),
+            "unknown option `--turbo`"
+        );
+        assert_eq!(
+            usage_error("game.ch8 --variant chip9"),
+            "unknown variant `chip9`"
+        );
+    }
+
+    #[test]
+    fn rejects_missing_values_and_roms() {
+        assert_eq!(usage_error("game.ch8 --seed"), "`--seed` needs a value");
+        assert_eq!(usage_error("run"), "no ROM given");
+        assert_eq!(usage_error("run --paused"), "no ROM given");
+        assert_eq!(
+            usage_error("game.ch8 other.ch8"),
+            "unexpected argument `other.ch8`, only one ROM can be given"
+        );
+    }
+}
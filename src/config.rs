@@ -0,0 +1,85 @@
+use crate::audio::Tone;
+use crate::display::{Palette, DEFAULT_PALETTE};
+use crate::font::{FontSet, FONT_BASE};
+use crate::memory::{OutOfBounds, MEMORY_SIZE};
+use crate::quirks::Quirks;
+use crate::rng::RngKind;
+use crate::stack::StackConfig;
+
+/// Size of the XO-CHIP address space.
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+
+/// The default CPU speed, roughly 600 instructions per second.
+pub const INSTRUCTIONS_PER_FRAME: usize = 10;
+
+/// The instruction set of the machine, each variant extends the previous one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Variant {
+    /// The original CHIP-8.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: 128x64 mode, scrolling, 16x16 sprites and a big font.
+    SuperChip,
+    /// XO-CHIP: 64 KiB of memory, 2 bitplanes and audio patterns.
+    XoChip,
+}
+
+/// Machine options, they are applied every time a ROM is loaded.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub variant: Variant,
+    pub stack: StackConfig,
+    /// Size of the address space in bytes, 4 KiB on the original machine.
+    pub memory_size: usize,
+    pub out_of_bounds: OutOfBounds,
+    pub font: FontSet,
+    /// Address of the glyph for `0`, the others follow it.
+    pub font_base: usize,
+    pub quirks: Quirks,
+    /// CPU speed, how many instructions run in each 60 Hz frame.
+    pub instructions_per_frame: usize,
+    /// Colors of the screen, see [`Palette`].
+    pub palette: Palette,
+    /// The sound of the buzzer.
+    pub tone: Tone,
+    /// Seed of the random number generator, taken from the clock when `None`.
+    pub seed: Option<u64>,
+    pub rng: RngKind,
+}
+
+impl Config {
+    /// The default options with the memory size and quirks of `variant`.
+    pub fn for_variant(variant: Variant) -> Config {
+        let (memory_size, quirks) = match variant {
+            Variant::Chip8 => (MEMORY_SIZE, Quirks::COSMAC_VIP),
+            Variant::SuperChip => (MEMORY_SIZE, Quirks::SUPER_CHIP),
+            Variant::XoChip => (XO_CHIP_MEMORY_SIZE, Quirks::XO_CHIP),
+        };
+
+        Config {
+            variant,
+            memory_size,
+            quirks,
+            ..Config::default()
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            variant: Variant::default(),
+            stack: StackConfig::default(),
+            memory_size: MEMORY_SIZE,
+            out_of_bounds: OutOfBounds::default(),
+            font: FontSet::default(),
+            font_base: FONT_BASE,
+            quirks: Quirks::default(),
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            palette: DEFAULT_PALETTE,
+            tone: Tone::default(),
+            seed: None,
+            rng: RngKind::default(),
+        }
+    }
+}
@@ -0,0 +1,1233 @@
+use crate::config::{Config, Variant};
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::font::{BIG_FONT, BIG_GLYPH_SIZE, GLYPH_SIZE};
+use crate::instruction::{DecodeError, Instruction};
+use crate::keypad::Keypad;
+use crate::memory::{OutOfBounds, PROGRAM_START};
+use crate::rng::Rng;
+use crate::stack::CallStack;
+
+/// Whether an instruction read or wrote memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A byte of memory an instruction read or wrote, see [`Chip8::set_memory_tracing`].
+///
+/// [`Chip8::set_memory_tracing`]: crate::Chip8::set_memory_tracing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// Address of the instruction.
+    pub pc: usize,
+    pub addr: usize,
+    pub kind: AccessKind,
+    /// The value read or written.
+    pub value: u8,
+    /// The value before a write, the same as `value` for a read.
+    pub previous: u8,
+}
+
+/// Raw state of the interpreter, everything an instruction can touch lives here.
+#[allow(non_snake_case)]
+#[derive(Clone)]
+pub struct CpuState {
+    /// Program Counter, counts the current instruction.
+    pub pc: usize,
+
+    /// Return addresses of `CALL`
+    pub stack: CallStack,
+
+    /// I register
+    pub I: u16,
+
+    /// V0..VF registers
+    pub V: [u8; 17],
+
+    /// Delay timer, counts down to 0 at 60 Hz.
+    pub delay: u8,
+    /// Sound timer, counts down to 0 at 60 Hz, the buzzer sounds while it's not 0.
+    pub sound: u8,
+
+    /// Main memory
+    pub mem: Vec<u8>,
+    pub display: Display,
+
+    /// SUPER-CHIP "RPL user flags", saved and restored by `Fx75`/`Fx85`.
+    pub rpl: [u8; 16],
+
+    /// XO-CHIP 1-bit audio pattern, 128 samples played while the sound timer runs.
+    /// The plain buzzer plays until `F002` loads one.
+    pub audio_pattern: Option<[u8; 16]>,
+    /// XO-CHIP pitch register, the pattern plays at `4000 * 2^((pitch - 64) / 48)` Hz.
+    pub pitch: u8,
+
+    /// Source of `Cxkk`.
+    pub rng: Rng,
+
+    pub keypad: Keypad,
+    /// The key `Fx0A` saw going down, it waits for it to be released.
+    pub waiting_key: Option<u8>,
+
+    /// The memory the last instruction read and wrote, only recorded when it's `Some`.
+    /// Fetching instructions and the stack don't count.
+    pub(crate) accesses: Option<Vec<MemoryAccess>>,
+}
+
+impl CpuState {
+    /// Creates the machine state with the ROM `m` loaded at `0x200`.
+    ///
+    /// Fails if the fonts of `config` don't fit below `0x200`, if its memory can't hold
+    /// the area below `0x200`, or if the ROM doesn't fit after it, before the stack when
+    /// the stack is in memory.
+    pub fn new(m: &[u8], config: &Config) -> Result<CpuState, Chip8Error> {
+        // The big font goes right after the small one.
+        let font = config.font.glyphs();
+        let big_font_base = config.font_base + font.len();
+        let font_end = big_font_base + BIG_FONT.len();
+        if font_end > PROGRAM_START {
+            return Err(Chip8Error::FontOverlapsProgram {
+                start: config.font_base,
+                end: font_end,
+            });
+        }
+
+        if config.memory_size < PROGRAM_START {
+            return Err(Chip8Error::MemoryTooSmall {
+                size: config.memory_size,
+                min: PROGRAM_START,
+            });
+        }
+
+        // A stack kept in memory sits after the program area.
+        let program_end = match config.stack.ram_base {
+            Some(base) if base >= PROGRAM_START => base.min(config.memory_size),
+            _ => config.memory_size,
+        };
+        let max = program_end - PROGRAM_START;
+        if m.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: m.len(), max });
+        }
+
+        let mut mem = vec![0; config.memory_size];
+
+        mem[PROGRAM_START..(m.len() + PROGRAM_START)].copy_from_slice(m);
+        mem[config.font_base..big_font_base].copy_from_slice(font);
+        mem[big_font_base..font_end].copy_from_slice(&BIG_FONT);
+
+        let rng = match config.seed {
+            Some(seed) => Rng::new(config.rng, seed),
+            None => Rng::from_time(config.rng),
+        };
+
+        let mut display = Display::new();
+        display.set_palette(config.palette);
+
+        Ok(CpuState {
+            pc: PROGRAM_START,
+            stack: CallStack::new(config.stack),
+            I: 0,
+            V: [0; 17],
+            delay: 0,
+            sound: 0,
+            mem,
+            display,
+            rpl: [0; 16],
+            audio_pattern: None,
+            pitch: 64,
+            rng,
+            keypad: Keypad::new(),
+            waiting_key: None,
+            accesses: None,
+        })
+    }
+
+    /// Decrements the delay and sound timers and advances the random generator, has to
+    /// be called at 60 Hz.
+    pub fn tick_timers(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+        self.rng.tick();
+    }
+
+    /// Reads the big endian opcode stored at `addr`.
+    pub fn opcode_at(&self, addr: usize) -> u16 {
+        ((self.read(addr) as u16) << 8) | self.read(addr + 1) as u16
+    }
+
+    /// Decodes the instruction at `addr`, reading the second word of `F000 nnnn`.
+    pub fn instruction_at(&self, addr: usize) -> Result<Instruction, DecodeError> {
+        Instruction::decode_long(self.opcode_at(addr), self.opcode_at(addr + 2))
+    }
+
+    /// Reads memory, addresses past the end wrap around.
+    pub fn read(&self, addr: usize) -> u8 {
+        self.mem[addr % self.mem.len()]
+    }
+
+    /// Reads memory for an instruction, recording the access.
+    fn load(&mut self, addr: usize) -> u8 {
+        let value = self.read(addr);
+        self.record(addr, AccessKind::Read, value, value);
+
+        value
+    }
+
+    fn write(&mut self, addr: usize, value: u8) {
+        let addr = addr % self.mem.len();
+        let previous = self.mem[addr];
+        self.mem[addr] = value;
+        self.record(addr, AccessKind::Write, value, previous);
+    }
+
+    fn record(&mut self, addr: usize, kind: AccessKind, value: u8, previous: u8) {
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.push(MemoryAccess {
+                pc: self.pc,
+                addr: addr % self.mem.len(),
+                kind,
+                value,
+                previous,
+            });
+        }
+    }
+
+    /// Checks that `len` bytes starting at `addr` are inside memory, unless the config
+    /// says that addresses wrap around.
+    fn check_mem(&self, addr: usize, len: usize, config: &Config) -> Result<(), Chip8Error> {
+        if config.out_of_bounds == OutOfBounds::Error && addr + len > self.mem.len() {
+            let first_bad = addr.max(self.mem.len());
+            return Err(Chip8Error::MemoryOutOfBounds { addr: first_bad });
+        }
+
+        Ok(())
+    }
+
+    /// The logic operations clear VF with the vf-reset quirk.
+    fn reset_vf(&mut self, config: &Config) {
+        if config.quirks.vf_reset {
+            self.V[0xF] = 0;
+        }
+    }
+
+    /// Keeps `I` inside the address space when addresses wrap around.
+    fn wrap_i(&mut self, config: &Config) {
+        if config.out_of_bounds == OutOfBounds::Wrap {
+            self.I = (self.I as usize % self.mem.len()) as u16;
+        }
+    }
+
+    /// Moves `pc` past the next instruction, on XO-CHIP it may be 4 bytes long.
+    fn skip_next(&mut self, config: &Config) {
+        let long = config.variant >= Variant::XoChip && self.opcode_at(self.pc + 2) == 0xF000;
+
+        self.pc += if long { 4 } else { 2 };
+    }
+
+    /// Executes the instruction at `pc`.
+    ///
+    /// When an error is returned the state is left untouched, so the caller can decide
+    /// whether to stop or skip the instruction.
+    pub fn emulate_chip8(&mut self, config: &Config) -> Result<Instruction, Chip8Error> {
+        if self.pc + 1 >= self.mem.len() {
+            match config.out_of_bounds {
+                OutOfBounds::Wrap => self.pc %= self.mem.len(),
+                OutOfBounds::Error => return Err(Chip8Error::PcOutOfBounds { pc: self.pc }),
+            }
+        }
+
+        let opcode = self.opcode_at(self.pc);
+
+        let unknown = Chip8Error::UnknownOpcode {
+            addr: self.pc,
+            opcode,
+        };
+
+        let instruction = self.instruction_at(self.pc).map_err(|_| unknown)?;
+
+        // Instructions of later variants aren't available on this machine.
+        if instruction.variant() > config.variant {
+            return Err(unknown);
+        }
+
+        self.execute(instruction, config)?;
+
+        Ok(instruction)
+    }
+
+    fn execute(&mut self, instruction: Instruction, config: &Config) -> Result<(), Chip8Error> {
+        match instruction {
+            Instruction::Cls => {
+                self.display.clear();
+
+                self.pc += 2;
+            }
+
+            Instruction::ScrollDown(n) => {
+                self.display.scroll_down(n as usize);
+
+                self.pc += 2;
+            }
+
+            Instruction::ScrollUp(n) => {
+                self.display.scroll_up(n as usize);
+
+                self.pc += 2;
+            }
+
+            Instruction::ScrollRight => {
+                self.display.scroll_right(4);
+
+                self.pc += 2;
+            }
+
+            Instruction::ScrollLeft => {
+                self.display.scroll_left(4);
+
+                self.pc += 2;
+            }
+
+            Instruction::Exit => {
+                // The machine stops here, pc stays on the EXIT.
+            }
+
+            Instruction::LowRes => {
+                self.display.set_hires(false);
+
+                self.pc += 2;
+            }
+
+            Instruction::HighRes => {
+                self.display.set_hires(true);
+
+                self.pc += 2;
+            }
+
+            Instruction::Ret => {
+                //The interpreter sets the program counter to the
+                //address at the top of the stack.
+
+                let target = match self.stack.pop(&self.mem) {
+                    Some(target) => target,
+                    None => return Err(Chip8Error::StackUnderflow { addr: self.pc }),
+                };
+
+                self.pc = target as usize;
+            }
+
+            Instruction::Jump(addr) => {
+                self.pc = addr as usize;
+            }
+
+            Instruction::Call(addr) => {
+                // The interpreter puts the address of the next instruction
+                // on the top of the stack. The PC is then set to nnn.
+
+                if !self.stack.push((self.pc + 2) as u16, &mut self.mem) {
+                    return Err(Chip8Error::StackOverflow { addr: self.pc });
+                }
+
+                self.pc = addr as usize;
+            }
+
+            Instruction::SkipEqByte(x, kk) => {
+                // Skip next instruction if Vx = kk.
+                if self.V[x as usize] == kk {
+                    self.skip_next(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::SkipNeqByte(x, kk) => {
+                // Skip next instruction if Vx != kk.
+                if self.V[x as usize] != kk {
+                    self.skip_next(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::SkipEqReg(x, y) => {
+                // Skip next instruction if Vx = Vy.
+                if self.V[x as usize] == self.V[y as usize] {
+                    self.skip_next(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::SaveRange(x, y) => {
+                // Stores Vx..Vy at I, in reverse order when x > y. I is left untouched.
+                let regs = register_range(x, y);
+
+                self.check_mem(self.I as usize, regs.len(), config)?;
+
+                for (i, reg) in regs.into_iter().enumerate() {
+                    self.write(self.I as usize + i, self.V[reg]);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadRange(x, y) => {
+                let regs = register_range(x, y);
+
+                self.check_mem(self.I as usize, regs.len(), config)?;
+
+                for (i, reg) in regs.into_iter().enumerate() {
+                    self.V[reg] = self.load(self.I as usize + i);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadByte(x, kk) => {
+                self.V[x as usize] = kk;
+
+                self.pc += 2;
+            }
+
+            Instruction::AddByte(x, kk) => {
+                // Doesn't touch VF.
+                self.V[x as usize] = self.V[x as usize].wrapping_add(kk);
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadReg(x, y) => {
+                self.V[x as usize] = self.V[y as usize];
+
+                self.pc += 2;
+            }
+
+            Instruction::Or(x, y) => {
+                self.V[x as usize] |= self.V[y as usize];
+                self.reset_vf(config);
+
+                self.pc += 2;
+            }
+
+            Instruction::And(x, y) => {
+                self.V[x as usize] &= self.V[y as usize];
+                self.reset_vf(config);
+
+                self.pc += 2;
+            }
+
+            Instruction::Xor(x, y) => {
+                self.V[x as usize] ^= self.V[y as usize];
+                self.reset_vf(config);
+
+                self.pc += 2;
+            }
+
+            Instruction::AddReg(x, y) => {
+                //Set Vx = Vx + Vy, set VF = carry
+
+                let (res, carry) = self.V[x as usize].overflowing_add(self.V[y as usize]);
+
+                self.V[x as usize] = res;
+                self.V[0xF] = carry as u8;
+
+                self.pc += 2;
+            }
+
+            Instruction::Sub(x, y) => {
+                //Set Vx = Vx - Vy, set VF = NOT borrow.
+
+                let not_borrow = self.V[x as usize] >= self.V[y as usize];
+
+                self.V[x as usize] = self.V[x as usize].wrapping_sub(self.V[y as usize]);
+                self.V[0xF] = not_borrow as u8;
+
+                self.pc += 2;
+            }
+
+            Instruction::ShiftRight(x, y) => {
+                //If the least-significant bit of Vx is 1,
+                //then VF is set to 1, otherwise 0.
+                //Then Vx is divided by 2.
+                //Without the shift quirk Vy is the one being shifted.
+
+                let src = if config.quirks.shift { x } else { y };
+                let lsb = self.V[src as usize] & 1;
+
+                self.V[x as usize] = self.V[src as usize] >> 1;
+                self.V[0xF] = lsb;
+
+                self.pc += 2;
+            }
+
+            Instruction::SubN(x, y) => {
+                //Set Vx = Vy - Vx, set VF = NOT borrow.
+
+                let not_borrow = self.V[y as usize] >= self.V[x as usize];
+
+                self.V[x as usize] = self.V[y as usize].wrapping_sub(self.V[x as usize]);
+                self.V[0xF] = not_borrow as u8;
+
+                self.pc += 2;
+            }
+
+            Instruction::ShiftLeft(x, y) => {
+                //If the most-significant bit of Vx is 1,
+                //then VF is set to 1, otherwise 0.
+                //Then Vx is multiplied by 2.
+
+                let src = if config.quirks.shift { x } else { y };
+                let msb = self.V[src as usize] >> 7;
+
+                self.V[x as usize] = self.V[src as usize] << 1;
+                self.V[0xF] = msb;
+
+                self.pc += 2;
+            }
+
+            Instruction::SkipNeqReg(x, y) => {
+                if self.V[x as usize] != self.V[y as usize] {
+                    self.skip_next(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadI(addr) => {
+                // I register, used to store mem addresses.
+                self.I = addr;
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadLongI(addr) => {
+                self.I = addr;
+                self.wrap_i(config);
+
+                self.pc += 4;
+            }
+
+            Instruction::JumpV0(addr) => {
+                // With the jump quirk this is Bxnn, jump to xnn + Vx.
+                let reg = if config.quirks.jump {
+                    (addr >> 8) as usize
+                } else {
+                    0
+                };
+
+                self.pc = addr as usize + self.V[reg] as usize;
+            }
+
+            Instruction::Random(x, kk) => {
+                let r = self.rng.next_byte(&self.mem);
+
+                self.V[x as usize] = r & kk;
+
+                self.pc += 2;
+            }
+
+            Instruction::Draw(regx, regy, n) => {
+                // Dxyn - DRW Vx, Vy, nibble
+                // XORs an n bytes sprite from memory at I onto the screen at (Vx, Vy),
+                // VF is set if any pixel was turned off.
+                // SUPER-CHIP: Dxy0 draws a 16x16 sprite, 2 bytes per row.
+                // XO-CHIP: with both planes selected the sprite for plane 2 follows.
+                let wide = n == 0 && config.variant >= Variant::SuperChip;
+                let plane_len = if wide { 32 } else { n as usize };
+                let len = plane_len * self.display.selected_plane_count();
+
+                let x: usize = self.V[regx as usize] as usize;
+                let y: usize = self.V[regy as usize] as usize;
+
+                self.check_mem(self.I as usize, len, config)?;
+
+                let rows: Vec<u8> = (0..len).map(|i| self.load(self.I as usize + i)).collect();
+                let collisions =
+                    self.display
+                        .draw_sprite(x, y, &rows, wide, config.quirks.clipping);
+
+                // In high resolution SUPER-CHIP counts the rows that collided.
+                self.V[0xF] = if config.variant == Variant::SuperChip && self.display.is_hires() {
+                    collisions as u8
+                } else {
+                    (collisions > 0) as u8
+                };
+
+                self.pc += 2;
+            }
+
+            Instruction::SkipKey(x) => {
+                if self.keypad.is_down(self.V[x as usize] & 0xf) {
+                    self.skip_next(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::SkipNoKey(x) => {
+                if !self.keypad.is_down(self.V[x as usize] & 0xf) {
+                    self.skip_next(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::Plane(n) => {
+                self.display.select_planes(n);
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadAudio => {
+                let mut pattern = [0; 16];
+                self.check_mem(self.I as usize, pattern.len(), config)?;
+
+                for (i, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.load(self.I as usize + i);
+                }
+                self.audio_pattern = Some(pattern);
+
+                self.pc += 2;
+            }
+
+            Instruction::SetPitch(x) => {
+                self.pitch = self.V[x as usize];
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadDelay(x) => {
+                self.V[x as usize] = self.delay;
+
+                self.pc += 2;
+            }
+
+            Instruction::SetDelay(x) => {
+                self.delay = self.V[x as usize];
+
+                self.pc += 2;
+            }
+
+            Instruction::SetSound(x) => {
+                self.sound = self.V[x as usize];
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadFont(x) => {
+                let digit = (self.V[x as usize] & 0xf) as usize;
+                self.I = (config.font_base + digit * GLYPH_SIZE) as u16;
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadBigFont(x) => {
+                let digit = (self.V[x as usize] & 0xf) as usize;
+                let big_font_base = config.font_base + config.font.glyphs().len();
+                self.I = (big_font_base + digit * BIG_GLYPH_SIZE) as u16;
+
+                self.pc += 2;
+            }
+
+            Instruction::StoreFlags(x) => {
+                let x = x as usize;
+                self.rpl[..=x].copy_from_slice(&self.V[..=x]);
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadFlags(x) => {
+                let x = x as usize;
+                self.V[..=x].copy_from_slice(&self.rpl[..=x]);
+
+                self.pc += 2;
+            }
+
+            Instruction::StoreBcd(x) => {
+                let mut val: u8 = self.V[x as usize];
+
+                let ones: u8 = val % 10;
+                val /= 10;
+                let tens: u8 = val % 10;
+                val /= 10;
+                let hundreds: u8 = val % 10;
+
+                self.check_mem(self.I as usize, 3, config)?;
+
+                self.write(self.I as usize, hundreds);
+                self.write(self.I as usize + 1, tens);
+                self.write(self.I as usize + 2, ones);
+
+                self.pc += 2;
+            }
+
+            Instruction::StoreRegs(x) => {
+                let x = x as usize;
+                let base = self.I as usize;
+
+                self.check_mem(base, x + 1, config)?;
+
+                for i in 0..=x {
+                    self.write(base + i, self.V[i]);
+                }
+
+                if config.quirks.memory_increment {
+                    self.I = self.I.wrapping_add((x + 1) as u16);
+                    self.wrap_i(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::LoadRegs(x) => {
+                //Read registers V0 through Vx from memory starting at location I.
+
+                let x = x as usize;
+                let base = self.I as usize;
+
+                self.check_mem(base, x + 1, config)?;
+
+                for i in 0..=x {
+                    self.V[i] = self.load(base + i);
+                }
+
+                if config.quirks.memory_increment {
+                    self.I = self.I.wrapping_add((x + 1) as u16);
+                    self.wrap_i(config);
+                }
+
+                self.pc += 2;
+            }
+
+            Instruction::WaitKey(x) => {
+                // Like on the COSMAC VIP the key is only taken once it's released. Until
+                // then pc stays here, the timers keep running.
+                if self.waiting_key.is_none() {
+                    self.waiting_key = self.keypad.first_pressed();
+                }
+
+                if let Some(key) = self.waiting_key {
+                    if self.keypad.was_released(key) {
+                        self.keypad.consume(key);
+                        self.waiting_key = None;
+                        self.V[x as usize] = key;
+
+                        self.pc += 2;
+                    }
+                }
+            }
+
+            Instruction::AddI(x) => {
+                self.I = self.I.wrapping_add(self.V[x as usize] as u16);
+                self.wrap_i(config);
+
+                self.pc += 2;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The registers of `5xy2`/`5xy3`, from `x` to `y` in either direction.
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    let (x, y) = (x as usize, y as usize);
+
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack::StackConfig;
+
+    /// Runs `instruction` with `V0 = a` and `V1 = b`, returns `V0` and `VF`.
+    fn alu(instruction: Instruction, a: u8, b: u8) -> (u8, u8) {
+        let config = Config::default();
+        let mut cpu = CpuState::new(&[], &config).unwrap();
+        cpu.V[0] = a;
+        cpu.V[1] = b;
+
+        cpu.execute(instruction, &config).unwrap();
+        (cpu.V[0], cpu.V[0xF])
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        assert_eq!(alu(Instruction::AddReg(0, 1), 0x10, 0x20), (0x30, 0));
+        assert_eq!(alu(Instruction::AddReg(0, 1), 0xFF, 0x01), (0x00, 1));
+        assert_eq!(alu(Instruction::AddReg(0, 1), 0xF0, 0x20), (0x10, 1));
+    }
+
+    #[test]
+    fn sub_sets_not_borrow() {
+        assert_eq!(alu(Instruction::Sub(0, 1), 0x30, 0x10), (0x20, 1));
+        assert_eq!(alu(Instruction::Sub(0, 1), 0x10, 0x10), (0x00, 1));
+        assert_eq!(alu(Instruction::Sub(0, 1), 0x10, 0x30), (0xE0, 0));
+    }
+
+    #[test]
+    fn subn_sets_not_borrow() {
+        assert_eq!(alu(Instruction::SubN(0, 1), 0x10, 0x30), (0x20, 1));
+        assert_eq!(alu(Instruction::SubN(0, 1), 0x10, 0x10), (0x00, 1));
+        assert_eq!(alu(Instruction::SubN(0, 1), 0x30, 0x10), (0xE0, 0));
+    }
+
+    #[test]
+    fn shifts_set_the_shifted_bit() {
+        // Without the shift quirk Vy is shifted into Vx.
+        assert_eq!(alu(Instruction::ShiftRight(0, 1), 0x00, 0x03), (0x01, 1));
+        assert_eq!(alu(Instruction::ShiftRight(0, 1), 0x00, 0x02), (0x01, 0));
+        assert_eq!(alu(Instruction::ShiftLeft(0, 1), 0x00, 0x81), (0x02, 1));
+        assert_eq!(alu(Instruction::ShiftLeft(0, 1), 0x00, 0x41), (0x82, 0));
+    }
+
+    #[test]
+    fn rejects_memory_below_the_program_area() {
+        let config = Config {
+            memory_size: 0x100,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            CpuState::new(&[], &config).err(),
+            Some(Chip8Error::MemoryTooSmall {
+                size: 0x100,
+                min: PROGRAM_START
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_fonts_in_the_program_area() {
+        let config = Config {
+            font_base: 0x120,
+            ..Config::default()
+        };
+        let end = 0x120 + 16 * GLYPH_SIZE + BIG_FONT.len();
+
+        assert_eq!(
+            CpuState::new(&[], &config).err(),
+            Some(Chip8Error::FontOverlapsProgram { start: 0x120, end })
+        );
+
+        // The last base where both fonts fit.
+        let config = Config {
+            font_base: PROGRAM_START - 16 * (GLYPH_SIZE + BIG_GLYPH_SIZE),
+            ..Config::default()
+        };
+        let cpu = CpuState::new(&[0x12, 0x00], &config).unwrap();
+        assert_eq!(cpu.mem[PROGRAM_START - 1], BIG_FONT[BIG_FONT.len() - 1]);
+        assert_eq!(cpu.mem[PROGRAM_START..PROGRAM_START + 2], [0x12, 0x00]);
+    }
+
+    #[test]
+    fn rejects_roms_over_the_stack_in_memory() {
+        let config = Config {
+            stack: StackConfig::COSMAC_VIP,
+            ..Config::default()
+        };
+        let max = 0xEA0 - PROGRAM_START;
+
+        assert!(CpuState::new(&vec![0xAA; max], &config).is_ok());
+        assert_eq!(
+            CpuState::new(&vec![0xAA; max + 1], &config).err(),
+            Some(Chip8Error::RomTooLarge { size: max + 1, max })
+        );
+    }
+
+    #[test]
+    fn rejects_roms_past_the_end_of_memory() {
+        let config = Config::default();
+        let max = config.memory_size - PROGRAM_START;
+
+        assert!(CpuState::new(&vec![0xAA; max], &config).is_ok());
+        assert_eq!(
+            CpuState::new(&vec![0xAA; max + 1], &config).err(),
+            Some(Chip8Error::RomTooLarge { size: max + 1, max })
+        );
+    }
+
+    #[test]
+    fn flag_wins_over_the_result_in_vf() {
+        let config = Config::default();
+        let mut cpu = CpuState::new(&[], &config).unwrap();
+        cpu.V[0xF] = 0x10;
+        cpu.V[1] = 0x20;
+
+        cpu.execute(Instruction::Sub(0xF, 1), &config).unwrap();
+        assert_eq!(cpu.V[0xF], 0);
+    }
+
+    /// Draws `sprite` at (`x`, `y`) with `DRW V0, V1, n` and returns `VF`.
+    fn draw(cpu: &mut CpuState, config: &Config, x: u8, y: u8, sprite: &[u8]) -> u8 {
+        cpu.mem[0x300..0x300 + sprite.len()].copy_from_slice(sprite);
+        cpu.I = 0x300;
+        cpu.V[0] = x;
+        cpu.V[1] = y;
+
+        cpu.execute(Instruction::Draw(0, 1, sprite.len() as u8), config)
+            .unwrap();
+        cpu.V[0xF]
+    }
+
+    /// The lit pixels of the screen as (x, y).
+    fn lit(cpu: &CpuState) -> Vec<(usize, usize)> {
+        let w = cpu.display.width();
+        (0..cpu.display.planes().len())
+            .filter(|i| cpu.display.planes()[*i] != 0)
+            .map(|i| (i % w, i / w))
+            .collect()
+    }
+
+    fn clipping(clipping: bool) -> Config {
+        let mut config = Config::default();
+        config.quirks.clipping = clipping;
+        config
+    }
+
+    #[test]
+    fn draw_sets_vf_when_pixels_turn_off() {
+        let config = Config::default();
+        let mut cpu = CpuState::new(&[], &config).unwrap();
+
+        assert_eq!(draw(&mut cpu, &config, 0, 0, &[0xF0]), 0);
+        assert_eq!(lit(&cpu), [(0, 0), (1, 0), (2, 0), (3, 0)]);
+
+        // Next to the lit pixels, nothing turns off.
+        assert_eq!(draw(&mut cpu, &config, 0, 0, &[0x0F]), 0);
+        assert_eq!(lit(&cpu).len(), 8);
+
+        assert_eq!(draw(&mut cpu, &config, 3, 0, &[0x80]), 1);
+        assert_eq!(lit(&cpu).len(), 7);
+
+        // VF is cleared again by the next sprite that doesn't collide.
+        assert_eq!(draw(&mut cpu, &config, 0, 5, &[0x80]), 0);
+    }
+
+    #[test]
+    fn draw_clips_or_wraps_at_the_edges() {
+        let config = clipping(true);
+        let mut cpu = CpuState::new(&[], &config).unwrap();
+        draw(&mut cpu, &config, 62, 31, &[0xF0, 0xC0]);
+        assert_eq!(lit(&cpu), [(62, 31), (63, 31)]);
+
+        let config = clipping(false);
+        let mut cpu = CpuState::new(&[], &config).unwrap();
+        draw(&mut cpu, &config, 62, 31, &[0xF0, 0xC0]);
+        assert_eq!(
+            lit(&cpu),
+            [(62, 0), (63, 0), (0, 31), (1, 31), (62, 31), (63, 31)]
+        );
+    }
+
+    #[test]
+    fn draw_wraps_sprites_that_start_off_screen() {
+        for clip in [true, false] {
+            let config = clipping(clip);
+            let mut cpu = CpuState::new(&[], &config).unwrap();
+
+            draw(&mut cpu, &config, 64 + 10, 32 + 4, &[0x80]);
+            assert_eq!(lit(&cpu), [(10, 4)]);
+        }
+    }
+
+    fn quirk(name: &str, enabled: bool) -> Config {
+        let mut config = Config::default();
+        assert!(config.quirks.set(name, enabled));
+        config
+    }
+
+    /// Runs `instruction` on a machine prepared by `setup`.
+    fn run<F: FnOnce(&mut CpuState)>(
+        config: &Config,
+        instruction: Instruction,
+        setup: F,
+    ) -> CpuState {
+        let mut cpu = CpuState::new(&[], config).unwrap();
+        setup(&mut cpu);
+        cpu.execute(instruction, config).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn shift_quirk_shifts_vx_in_place() {
+        let setup = |cpu: &mut CpuState| {
+            cpu.V[0] = 0x04;
+            cpu.V[1] = 0x81;
+        };
+
+        let cpu = run(&quirk("shift", true), Instruction::ShiftRight(0, 1), setup);
+        assert_eq!((cpu.V[0], cpu.V[0xF]), (0x02, 0));
+        let cpu = run(&quirk("shift", false), Instruction::ShiftRight(0, 1), setup);
+        assert_eq!((cpu.V[0], cpu.V[0xF]), (0x40, 1));
+
+        let cpu = run(&quirk("shift", true), Instruction::ShiftLeft(0, 1), setup);
+        assert_eq!((cpu.V[0], cpu.V[0xF]), (0x08, 0));
+        let cpu = run(&quirk("shift", false), Instruction::ShiftLeft(0, 1), setup);
+        assert_eq!((cpu.V[0], cpu.V[0xF]), (0x02, 1));
+    }
+
+    #[test]
+    fn memory_increment_quirk_moves_i() {
+        let setup = |cpu: &mut CpuState| {
+            cpu.I = 0x300;
+            cpu.V[..3].copy_from_slice(&[1, 2, 3]);
+        };
+
+        let cpu = run(
+            &quirk("memory-increment", true),
+            Instruction::StoreRegs(2),
+            setup,
+        );
+        assert_eq!(cpu.I, 0x303);
+        assert_eq!(&cpu.mem[0x300..0x304], &[1, 2, 3, 0]);
+        let cpu = run(
+            &quirk("memory-increment", false),
+            Instruction::StoreRegs(2),
+            setup,
+        );
+        assert_eq!(cpu.I, 0x300);
+        assert_eq!(&cpu.mem[0x300..0x304], &[1, 2, 3, 0]);
+
+        let setup = |cpu: &mut CpuState| {
+            cpu.I = 0x300;
+            cpu.mem[0x300..0x302].copy_from_slice(&[7, 8]);
+        };
+        let cpu = run(
+            &quirk("memory-increment", true),
+            Instruction::LoadRegs(1),
+            setup,
+        );
+        assert_eq!((cpu.I, cpu.V[0], cpu.V[1]), (0x302, 7, 8));
+        let cpu = run(
+            &quirk("memory-increment", false),
+            Instruction::LoadRegs(1),
+            setup,
+        );
+        assert_eq!((cpu.I, cpu.V[0], cpu.V[1]), (0x300, 7, 8));
+    }
+
+    #[test]
+    fn jump_quirk_adds_vx() {
+        let setup = |cpu: &mut CpuState| {
+            cpu.V[0] = 0x01;
+            cpu.V[2] = 0x10;
+        };
+
+        let cpu = run(&quirk("jump", true), Instruction::JumpV0(0x234), setup);
+        assert_eq!(cpu.pc, 0x244);
+        let cpu = run(&quirk("jump", false), Instruction::JumpV0(0x234), setup);
+        assert_eq!(cpu.pc, 0x235);
+    }
+
+    #[test]
+    fn vf_reset_quirk_clears_vf_after_logic() {
+        let setup = |cpu: &mut CpuState| {
+            cpu.V[0] = 0x0C;
+            cpu.V[1] = 0x0A;
+            cpu.V[0xF] = 0x55;
+        };
+
+        for (instruction, result) in [
+            (Instruction::Or(0, 1), 0x0E),
+            (Instruction::And(0, 1), 0x08),
+            (Instruction::Xor(0, 1), 0x06),
+        ] {
+            let cpu = run(&quirk("vf-reset", true), instruction, setup);
+            assert_eq!((cpu.V[0], cpu.V[0xF]), (result, 0));
+            let cpu = run(&quirk("vf-reset", false), instruction, setup);
+            assert_eq!((cpu.V[0], cpu.V[0xF]), (result, 0x55));
+        }
+    }
+
+    /// A `variant` machine with `rom` loaded.
+    fn boot(variant: Variant, rom: &[u8]) -> (CpuState, Config) {
+        let config = Config::for_variant(variant);
+        (CpuState::new(rom, &config).unwrap(), config)
+    }
+
+    fn steps(cpu: &mut CpuState, config: &Config, n: usize) {
+        for _ in 0..n {
+            cpu.emulate_chip8(config).unwrap();
+        }
+    }
+
+    #[test]
+    fn super_chip_scrolls() {
+        let rom = [
+            0x00, 0xFF, // HIGH
+            0xA2, 0x10, // LD I, $210
+            0xD0, 0x01, // DRW V0, V0, 1
+            0x00, 0xC3, // SCD 3
+            0x00, 0xFB, // SCR
+            0x00, 0xFB, // SCR
+            0x00, 0xFC, // SCL
+            0x00, 0xFE, // LOW
+            0x80, 0x00, // the sprite
+        ];
+        let (mut cpu, config) = boot(Variant::SuperChip, &rom);
+
+        steps(&mut cpu, &config, 3);
+        assert!(cpu.display.is_hires());
+        assert_eq!(cpu.display.width(), 128);
+        assert_eq!(lit(&cpu), [(0, 0)]);
+
+        for expected in [(0, 3), (4, 3), (8, 3), (4, 3)] {
+            steps(&mut cpu, &config, 1);
+            assert_eq!(lit(&cpu), [expected]);
+        }
+
+        steps(&mut cpu, &config, 1);
+        assert!(!cpu.display.is_hires());
+        assert_eq!(cpu.display.width(), 64);
+    }
+
+    #[test]
+    fn super_chip_draws_16x16_sprites() {
+        let mut rom = vec![
+            0x00, 0xFF, // HIGH
+            0xA2, 0x08, // LD I, $208
+            0xD0, 0x00, // DRW V0, V0, 0
+            0xD0, 0x00, // DRW V0, V0, 0
+        ];
+        rom.extend_from_slice(&[0xFF; 32]);
+        let (mut cpu, config) = boot(Variant::SuperChip, &rom);
+
+        steps(&mut cpu, &config, 3);
+        let pixels = lit(&cpu);
+        assert_eq!(pixels.len(), 16 * 16);
+        assert!(pixels.iter().all(|(x, y)| *x < 16 && *y < 16));
+        assert_eq!(cpu.V[0xF], 0);
+
+        // In high resolution VF counts the rows that collided.
+        steps(&mut cpu, &config, 1);
+        assert!(lit(&cpu).is_empty());
+        assert_eq!(cpu.V[0xF], 16);
+    }
+
+    #[test]
+    fn super_chip_flag_registers() {
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, // LD V0..V2, 1..3
+            0xF2, 0x75, // LD R, V2
+            0x60, 0x00, 0x61, 0x00, 0x62, 0x00, // LD V0..V2, 0
+            0xF1, 0x85, // LD V1, R
+        ];
+        let (mut cpu, config) = boot(Variant::SuperChip, &rom);
+
+        steps(&mut cpu, &config, 8);
+        assert_eq!(&cpu.V[..3], &[1, 2, 0]);
+        assert_eq!(&cpu.rpl[..4], &[1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn super_chip_instructions_need_super_chip() {
+        let (mut cpu, config) = boot(Variant::Chip8, &[0x00, 0xFF]);
+
+        assert_eq!(
+            cpu.emulate_chip8(&config),
+            Err(Chip8Error::UnknownOpcode {
+                addr: 0x200,
+                opcode: 0x00FF
+            })
+        );
+        assert!(!cpu.display.is_hires());
+    }
+
+    #[test]
+    fn xo_chip_long_i() {
+        let (mut cpu, config) = boot(Variant::XoChip, &[0xF0, 0x00, 0xAB, 0xCD]);
+        steps(&mut cpu, &config, 1);
+        assert_eq!((cpu.I, cpu.pc), (0xABCD, 0x204));
+
+        let (mut cpu, config) = boot(Variant::SuperChip, &[0xF0, 0x00, 0xAB, 0xCD]);
+        assert!(cpu.emulate_chip8(&config).is_err());
+    }
+
+    #[test]
+    fn xo_chip_register_ranges() {
+        let rom = [
+            0xA3, 0x00, // LD I, $300
+            0x61, 0x0A, 0x62, 0x0B, 0x63, 0x0C, // LD V1..V3, A..C
+            0x51, 0x32, // SAVE V1 - V3
+            0x54, 0x63, // LOAD V4 - V6
+            0x59, 0x73, // LOAD V9 - V7
+        ];
+        let (mut cpu, config) = boot(Variant::XoChip, &rom);
+
+        steps(&mut cpu, &config, 7);
+        assert_eq!(&cpu.mem[0x300..0x304], &[0x0A, 0x0B, 0x0C, 0]);
+        assert_eq!(&cpu.V[4..7], &[0x0A, 0x0B, 0x0C]);
+        assert_eq!(&cpu.V[7..10], &[0x0C, 0x0B, 0x0A]);
+        assert_eq!(cpu.I, 0x300);
+    }
+
+    #[test]
+    fn xo_chip_planes() {
+        let rom = [
+            0xA3, 0x00, // LD I, $300
+            0xF2, 0x01, // PLANE 2
+            0xD0, 0x01, // DRW V0, V0, 1
+            0xF3, 0x01, // PLANE 3
+            0xD0, 0x01, // DRW V0, V0, 1
+        ];
+        let (mut cpu, config) = boot(Variant::XoChip, &rom);
+        cpu.mem[0x300..0x302].copy_from_slice(&[0x80, 0xC0]);
+
+        steps(&mut cpu, &config, 3);
+        assert_eq!(&cpu.display.planes()[..2], &[2, 0]);
+
+        // With both planes the sprite of the second plane follows the first one.
+        steps(&mut cpu, &config, 2);
+        assert_eq!(&cpu.display.planes()[..2], &[1, 2]);
+        assert_eq!(cpu.V[0xF], 1);
+    }
+
+    #[test]
+    fn xo_chip_audio() {
+        let rom = [
+            0xA3, 0x00, // LD I, $300
+            0xF0, 0x02, // AUDIO
+            0x60, 0x70, // LD V0, #$70
+            0xF0, 0x3A, // PITCH V0
+        ];
+        let (mut cpu, config) = boot(Variant::XoChip, &rom);
+        let pattern: Vec<u8> = (0..16).map(|i| i * 17).collect();
+        cpu.mem[0x300..0x310].copy_from_slice(&pattern);
+
+        steps(&mut cpu, &config, 4);
+        assert_eq!(cpu.audio_pattern.map(|p| p.to_vec()), Some(pattern));
+        assert_eq!(cpu.pitch, 0x70);
+    }
+
+    #[test]
+    fn skips_jump_over_long_i() {
+        let skip_eq = [0x30, 0x05];
+        let skip_key = [0xE0, 0x9E];
+
+        for skip in [skip_eq, skip_key] {
+            let mut rom = vec![0x60, 0x05];
+            rom.extend_from_slice(&skip);
+            // LD I, #$1234; LD V1, 1
+            rom.extend_from_slice(&[0xF0, 0x00, 0x12, 0x34, 0x61, 0x01]);
+
+            let (mut cpu, config) = boot(Variant::XoChip, &rom);
+            cpu.keypad.press(5);
+            steps(&mut cpu, &config, 3);
+            assert_eq!(cpu.pc, 0x20A);
+            assert_eq!((cpu.I, cpu.V[1]), (0, 1));
+
+            // Before XO-CHIP F000 is a single word, the skip lands on its address.
+            let (mut cpu, config) = boot(Variant::SuperChip, &rom);
+            cpu.keypad.press(5);
+            steps(&mut cpu, &config, 2);
+            assert_eq!(cpu.pc, 0x206);
+        }
+    }
+}
@@ -0,0 +1,667 @@
+use crate::cpu::{AccessKind, MemoryAccess};
+use crate::error::Chip8Error;
+use crate::expr::{Expr, Template};
+use crate::instruction::Instruction;
+use crate::machine::{Chip8, StepOutcome};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+/// Why the debugger paused the machine, see [`Debugger::run_frame`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program counter reached a breakpoint, the instruction wasn't executed yet.
+    Breakpoint(usize),
+    /// An instruction touched the memory of a watchpoint, it was executed.
+    Watchpoint(MemoryAccess),
+    /// A step, step over, step out or run to finished.
+    Done,
+    /// The instruction at the program counter failed.
+    Error(Chip8Error),
+    /// The ROM ended with the SUPER-CHIP `EXIT` instruction.
+    Exited,
+}
+
+/// A breakpoint, or a tracepoint when it has a message.
+///
+/// The program counter reaching it is a hit when there is no condition or the
+/// condition is true. The first `ignore` hits are let through, the next ones pause the
+/// machine or log the message.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub condition: Option<Expr>,
+    pub ignore: usize,
+    /// Logs this message, see [`Debugger::take_log`], instead of pausing.
+    pub message: Option<Template>,
+    /// The hits so far, including the ignored ones.
+    pub hits: usize,
+}
+
+impl Breakpoint {
+    /// A breakpoint that pauses every time.
+    pub fn new() -> Breakpoint {
+        Breakpoint::default()
+    }
+
+    pub fn is_tracepoint(&self) -> bool {
+        self.message.is_some()
+    }
+}
+
+/// Something that happened without pausing the machine, see [`Debugger::take_log`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogEntry {
+    /// An access of a [`WatchAction::Log`] watchpoint.
+    Access(MemoryAccess),
+    /// The message of the tracepoint at `pc`, before the instruction was executed.
+    Trace { pc: usize, message: String },
+}
+
+/// The memory accesses a watchpoint reacts to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// The writes that change the value.
+    Change,
+    /// Reads and writes.
+    Access,
+}
+
+/// What a watchpoint does when it's hit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchAction {
+    /// Pauses the machine.
+    Break,
+    /// Adds the access to the log, see [`Debugger::take_log`].
+    Log,
+}
+
+/// Watches the instructions that read or write some memory, like the score of a game.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addrs: RangeInclusive<usize>,
+    pub kind: WatchKind,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    pub fn new(addrs: RangeInclusive<usize>, kind: WatchKind, action: WatchAction) -> Watchpoint {
+        Watchpoint {
+            addrs,
+            kind,
+            action,
+        }
+    }
+
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind = match (self.kind, access.kind) {
+            (WatchKind::Read, AccessKind::Read) | (WatchKind::Write, AccessKind::Write) => true,
+            (WatchKind::Change, AccessKind::Write) => access.value != access.previous,
+            (WatchKind::Access, _) => true,
+            _ => false,
+        };
+
+        kind && self.addrs.contains(&access.addr)
+    }
+}
+
+/// What the machine runs until, once resumed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    /// Until a breakpoint.
+    None,
+    /// One instruction.
+    Step,
+    /// Until the program counter is at `pc` with `depth` return addresses on the stack.
+    Return { pc: usize, depth: usize },
+    /// Until the stack is shallower than `depth`.
+    Out { depth: usize },
+    /// Until the program counter is at the address.
+    Address(usize),
+}
+
+/// Pauses, steps and breaks the machine, for frontends to build a debugger on.
+///
+/// The frontend calls [`Debugger::run_frame`] instead of [`Chip8::run_frame`] once per
+/// frame, and the commands (`pause`, `step`, `step_over`...) whenever the user asks.
+/// The commands that can take longer than an instruction resume the machine, which
+/// pauses again when they're done.
+///
+/// ```no_run
+/// let mut chip8 = chip8::Chip8::new();
+/// let mut debugger = chip8::Debugger::new();
+/// debugger.add_breakpoint(0x2a0);
+///
+/// loop {
+///     if let Some(reason) = debugger.run_frame(&mut chip8) {
+///         println!("{:?}\n{}", reason, debugger.registers_view(&chip8));
+///         debugger.step_over(&chip8);
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Debugger {
+    paused: bool,
+    target: Target,
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// The oldest first.
+    log: Vec<LogEntry>,
+    /// Set by the commands that resume, so resuming on a breakpoint doesn't break on
+    /// it again right away.
+    resumed: bool,
+}
+
+impl Debugger {
+    /// A running debugger without any breakpoint.
+    pub fn new() -> Debugger {
+        Debugger {
+            paused: false,
+            target: Target::None,
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            log: Vec::new(),
+            resumed: false,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops the machine before its next instruction, cancelling any step in progress.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.target = Target::None;
+    }
+
+    /// Runs until a breakpoint.
+    pub fn resume(&mut self) {
+        self.run_until(Target::None);
+    }
+
+    /// Pauses or resumes.
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    /// Executes one instruction, then pauses.
+    pub fn step(&mut self) {
+        self.run_until(Target::Step);
+    }
+
+    /// Like [`Debugger::step`], but a `CALL` runs the whole subroutine.
+    pub fn step_over(&mut self, chip8: &Chip8) {
+        let cpu = chip8.cpu();
+
+        match cpu.instruction_at(cpu.pc) {
+            Ok(instruction @ Instruction::Call(_)) => self.run_until(Target::Return {
+                pc: cpu.pc + instruction.size(),
+                depth: cpu.stack.depth(),
+            }),
+            _ => self.step(),
+        }
+    }
+
+    /// Runs until the current subroutine returns. Returns `false`, and does nothing,
+    /// outside of a subroutine.
+    pub fn step_out(&mut self, chip8: &Chip8) -> bool {
+        let depth = chip8.cpu().stack.depth();
+        if depth == 0 {
+            return false;
+        }
+
+        self.run_until(Target::Out { depth });
+        true
+    }
+
+    /// Runs until the program counter reaches `addr`, or a breakpoint.
+    pub fn run_to(&mut self, addr: usize) {
+        self.run_until(Target::Address(addr));
+    }
+
+    fn run_until(&mut self, target: Target) {
+        self.paused = false;
+        self.target = target;
+        self.resumed = true;
+    }
+
+    /// Pauses before the instruction at `addr`. Keeps the breakpoint already there, if
+    /// any.
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.entry(addr).or_default();
+    }
+
+    /// Adds a conditional breakpoint or a tracepoint, replacing the one at `addr`.
+    pub fn set_breakpoint(&mut self, addr: usize, breakpoint: Breakpoint) {
+        self.breakpoints.insert(addr, breakpoint);
+    }
+
+    /// Returns the breakpoint that was at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> Option<Breakpoint> {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Adds or removes the breakpoint at `addr`, returns whether there is one now.
+    pub fn toggle_breakpoint(&mut self, addr: usize) -> bool {
+        if self.breakpoints.remove(&addr).is_none() {
+            self.add_breakpoint(addr);
+            return true;
+        }
+
+        false
+    }
+
+    pub fn has_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints.contains_key(&addr)
+    }
+
+    /// The breakpoint at `addr`, with its hits.
+    pub fn breakpoint(&self, addr: usize) -> Option<&Breakpoint> {
+        self.breakpoints.get(&addr)
+    }
+
+    /// The addresses of the breakpoints, in order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Watches some memory. The debugger records the memory accesses of the machine
+    /// while there are watchpoints, see [`Chip8::set_memory_tracing`].
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the watchpoint at `index` in [`Debugger::watchpoints`].
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// The watchpoints, in the order they were added.
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// The accesses of the [`WatchAction::Log`] watchpoints and the messages of the
+    /// tracepoints since the last call, the oldest first. Frontends should take them
+    /// every frame.
+    pub fn take_log(&mut self) -> Vec<LogEntry> {
+        std::mem::take(&mut self.log)
+    }
+
+    /// Runs the rest of the frame unless the machine is paused. Returns why it paused,
+    /// when it did during this frame.
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        chip8.set_memory_tracing(!self.watchpoints.is_empty());
+
+        while !self.paused {
+            if let Some(reason) = self.step_once(chip8) {
+                self.pause();
+                return Some(reason);
+            }
+
+            if chip8.frame_cycles() == 0 {
+                break;
+            }
+        }
+
+        None
+    }
+
+    /// Executes the next instruction unless a breakpoint is in the way, and says if the
+    /// machine has to pause.
+    fn step_once(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        let pc = chip8.cpu().pc;
+        let resumed = std::mem::replace(&mut self.resumed, false);
+
+        if !resumed && self.hit_breakpoint(chip8, pc) {
+            return Some(StopReason::Breakpoint(pc));
+        }
+
+        match chip8.step() {
+            Ok(StepOutcome::Halted) if chip8.exited() => return Some(StopReason::Exited),
+            Ok(StepOutcome::Halted) => return chip8.halted().map(StopReason::Error),
+            Ok(StepOutcome::Executed(Instruction::Exit)) => return Some(StopReason::Exited),
+            Ok(_) => {}
+            Err(e) => return Some(StopReason::Error(e)),
+        }
+
+        if let Some(access) = self.watch(chip8.memory_accesses()) {
+            return Some(StopReason::Watchpoint(access));
+        }
+
+        let cpu = chip8.cpu();
+        let done = match self.target {
+            Target::None => false,
+            Target::Step => true,
+            Target::Return { pc, depth } => cpu.pc == pc && cpu.stack.depth() == depth,
+            Target::Out { depth } => cpu.stack.depth() < depth,
+            Target::Address(addr) => cpu.pc == addr,
+        };
+
+        if done {
+            Some(StopReason::Done)
+        } else {
+            None
+        }
+    }
+
+    /// Counts a hit of the breakpoint at `pc`, if any, and says if it pauses. The
+    /// tracepoints log their message instead.
+    fn hit_breakpoint(&mut self, chip8: &Chip8, pc: usize) -> bool {
+        let cpu = chip8.cpu();
+        let breakpoint = match self.breakpoints.get_mut(&pc) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+
+        if let Some(condition) = &breakpoint.condition {
+            if !condition.is_true(cpu) {
+                return false;
+            }
+        }
+
+        breakpoint.hits += 1;
+        if breakpoint.hits <= breakpoint.ignore {
+            return false;
+        }
+
+        match &breakpoint.message {
+            Some(message) => {
+                let message = message.render(cpu);
+                self.log.push(LogEntry::Trace { pc, message });
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Logs the accesses the watchpoints want logged, and returns the first one that
+    /// breaks.
+    fn watch(&mut self, accesses: &[MemoryAccess]) -> Option<MemoryAccess> {
+        let watchpoints = &self.watchpoints;
+        let mut hit = None;
+
+        for access in accesses {
+            let matching = || watchpoints.iter().filter(|w| w.matches(access));
+
+            if matching().any(|w| w.action == WatchAction::Log) {
+                self.log.push(LogEntry::Access(*access));
+            }
+            if hit.is_none() && matching().any(|w| w.action == WatchAction::Break) {
+                hit = Some(*access);
+            }
+        }
+
+        hit
+    }
+
+    /// `V0` to `VF`, `I`, the timers and the stack, see [`CpuState::dump_registers`].
+    ///
+    /// [`CpuState::dump_registers`]: crate::CpuState::dump_registers
+    pub fn registers_view(&self, chip8: &Chip8) -> String {
+        chip8.cpu().dump_registers()
+    }
+
+    /// `lines` instructions starting a few before the program counter, with `>` on the
+    /// program counter, `*` on the breakpoints and `+` on the tracepoints.
+    ///
+    /// Instructions have different sizes on XO-CHIP, and data can sit between them, so
+    /// the instructions before the program counter are a guess.
+    pub fn disassembly_view(&self, chip8: &Chip8, lines: usize) -> String {
+        let cpu = chip8.cpu();
+        let mut addr = cpu.pc.saturating_sub(lines / 2 * 2);
+        let mut view = String::new();
+
+        for _ in 0..lines {
+            let size = cpu.instruction_at(addr).map_or(2, |i| i.size());
+            let marker = if addr == cpu.pc { '>' } else { ' ' };
+            let breakpoint = match self.breakpoint(addr) {
+                Some(breakpoint) if breakpoint.is_tracepoint() => '+',
+                Some(_) => '*',
+                None => ' ',
+            };
+
+            view.push_str(&format!(
+                "{}{} {:04X}  {}\n",
+                marker,
+                breakpoint,
+                addr,
+                cpu.disassemble_at(addr)
+            ));
+            addr += size;
+        }
+
+        view
+    }
+
+    /// A hex dump of `rows` rows of 16 bytes around `addr`, the row of `addr` is marked
+    /// with `>`.
+    pub fn memory_view(&self, chip8: &Chip8, addr: usize, rows: usize) -> String {
+        let cpu = chip8.cpu();
+        let len = cpu.mem.len();
+        let last_row = len.saturating_sub(1) & !0xf;
+        let start = (addr & !0xf)
+            .saturating_sub(rows / 2 * 16)
+            .min(last_row.saturating_sub(rows.saturating_sub(1) * 16));
+        let mut view = String::new();
+
+        for row in (start..len).step_by(16).take(rows) {
+            let marker = if (row..row + 16).contains(&addr) {
+                '>'
+            } else {
+                ' '
+            };
+            let bytes: Vec<String> = cpu.mem[row..(row + 16).min(len)]
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+
+            view.push_str(&format!("{} {:04X}  {}\n", marker, row, bytes.join(" ")));
+        }
+
+        view
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `LD V1, 0; ADD V1, 1; JP $202`, the loop at `0202` counts in V1.
+    fn counting() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[0x61, 0x00, 0x71, 0x01, 0x12, 0x02])
+            .unwrap();
+
+        chip8
+    }
+
+    fn run(debugger: &mut Debugger, chip8: &mut Chip8) -> Option<StopReason> {
+        (0..10).find_map(|_| debugger.run_frame(chip8))
+    }
+
+    #[test]
+    fn conditional_breakpoint_with_hit_count() {
+        let mut chip8 = counting();
+        let mut debugger = Debugger::new();
+        let breakpoint = Breakpoint {
+            condition: Some(Expr::parse("V1 >= 3").unwrap()),
+            ignore: 2,
+            ..Breakpoint::new()
+        };
+        debugger.set_breakpoint(0x202, breakpoint);
+
+        // V1 is 3 and 4 on the ignored hits.
+        assert_eq!(
+            run(&mut debugger, &mut chip8),
+            Some(StopReason::Breakpoint(0x202))
+        );
+        assert_eq!(chip8.cpu().V[1], 5);
+        assert_eq!(debugger.breakpoint(0x202).unwrap().hits, 3);
+
+        // Resuming on it doesn't count a hit again.
+        debugger.resume();
+        assert_eq!(
+            run(&mut debugger, &mut chip8),
+            Some(StopReason::Breakpoint(0x202))
+        );
+        assert_eq!(chip8.cpu().V[1], 6);
+        assert_eq!(debugger.breakpoint(0x202).unwrap().hits, 4);
+    }
+
+    #[test]
+    fn tracepoints_log_instead_of_pausing() {
+        let mut chip8 = counting();
+        let mut debugger = Debugger::new();
+        let tracepoint = Breakpoint {
+            condition: Some(Expr::parse("V1 % 2 == 0").unwrap()),
+            message: Some(Template::parse("V1 = {V1}").unwrap()),
+            ..Breakpoint::new()
+        };
+        debugger.set_breakpoint(0x204, tracepoint);
+
+        // The 10 instructions of a frame reach 0204 with V1 from 1 to 4.
+        assert_eq!(debugger.run_frame(&mut chip8), None);
+        assert_eq!(chip8.cpu().V[1], 5);
+
+        let log = debugger.take_log();
+        let messages: Vec<_> = log
+            .iter()
+            .map(|entry| match entry {
+                LogEntry::Trace { pc: 0x204, message } => message.as_str(),
+                entry => panic!("unexpected {:?}", entry),
+            })
+            .collect();
+        assert_eq!(messages, ["V1 = 2", "V1 = 4"]);
+        assert_eq!(debugger.breakpoint(0x204).unwrap().hits, 2);
+    }
+
+    /// Stores `2A 07` at `0300` with `Fx55` and reads it back with `Fx65`, in a loop.
+    fn storing() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        let rom = [
+            0x60, 0x2A, // LD V0, #$2A
+            0x61, 0x07, // LD V1, 7
+            0xA3, 0x00, // LD I, $300
+            0xF1, 0x55, // LD [I], V1
+            0xA3, 0x00, // LD I, $300
+            0xF1, 0x65, // LD V1, [I]
+            0x12, 0x04, // JP $204
+        ];
+        chip8.load_rom(&rom).unwrap();
+
+        chip8
+    }
+
+    fn watching(addrs: RangeInclusive<usize>, kind: WatchKind, action: WatchAction) -> Debugger {
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(Watchpoint::new(addrs, kind, action));
+        debugger
+    }
+
+    #[test]
+    fn stores_hit_write_watchpoints() {
+        let mut chip8 = storing();
+        let mut debugger = watching(0x301..=0x301, WatchKind::Write, WatchAction::Break);
+
+        let access = MemoryAccess {
+            pc: 0x206,
+            addr: 0x301,
+            kind: AccessKind::Write,
+            value: 0x07,
+            previous: 0,
+        };
+        assert_eq!(
+            run(&mut debugger, &mut chip8),
+            Some(StopReason::Watchpoint(access))
+        );
+        // The instruction was executed.
+        assert_eq!(chip8.cpu().pc, 0x208);
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn loads_hit_read_watchpoints() {
+        let mut chip8 = storing();
+        let mut debugger = watching(0x300..=0x300, WatchKind::Read, WatchAction::Break);
+
+        let access = MemoryAccess {
+            pc: 0x20A,
+            addr: 0x300,
+            kind: AccessKind::Read,
+            value: 0x2A,
+            previous: 0x2A,
+        };
+        assert_eq!(
+            run(&mut debugger, &mut chip8),
+            Some(StopReason::Watchpoint(access))
+        );
+        assert_eq!(chip8.cpu().pc, 0x20C);
+    }
+
+    #[test]
+    fn change_watchpoints_ignore_writes_of_the_same_value() {
+        let mut chip8 = storing();
+        let mut debugger = watching(0x300..=0x301, WatchKind::Change, WatchAction::Break);
+
+        match run(&mut debugger, &mut chip8) {
+            Some(StopReason::Watchpoint(access)) => {
+                assert_eq!(
+                    (access.addr, access.previous, access.value),
+                    (0x300, 0, 0x2A)
+                )
+            }
+            reason => panic!("stopped for {:?}", reason),
+        }
+
+        // The loop keeps storing the same values.
+        debugger.resume();
+        assert_eq!(run(&mut debugger, &mut chip8), None);
+    }
+
+    #[test]
+    fn log_watchpoints_record_without_pausing() {
+        let mut chip8 = storing();
+        let mut debugger = watching(0x300..=0x300, WatchKind::Access, WatchAction::Log);
+
+        assert_eq!(debugger.run_frame(&mut chip8), None);
+        assert!(!debugger.is_paused());
+
+        let log = debugger.take_log();
+        assert!(log.len() >= 2);
+        for (i, entry) in log.iter().enumerate() {
+            let (pc, kind) = match entry {
+                LogEntry::Access(access) => (access.pc, access.kind),
+                entry => panic!("unexpected {:?}", entry),
+            };
+            // Writes by the store and reads by the load, one after the other.
+            let expected = [(0x206, AccessKind::Write), (0x20A, AccessKind::Read)];
+            assert_eq!((pc, kind), expected[i % 2]);
+        }
+        assert!(debugger.take_log().is_empty());
+    }
+}
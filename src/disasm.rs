@@ -0,0 +1,65 @@
+use crate::cpu::CpuState;
+
+impl CpuState {
+    /// Disassembles the whole memory, starting at the current program counter.
+    pub fn disassemble_chip8(&self) -> Vec<String> {
+        let mut pc = self.pc;
+        let mut listing = Vec::new();
+
+        while pc + 1 < self.mem.len() {
+            listing.push(self.disassemble_at(pc));
+            pc += self
+                .instruction_at(pc)
+                .map_or(2, |instruction| instruction.size());
+        }
+
+        listing
+    }
+
+    /// Disassembles the instruction at `pc` into its mnemonic.
+    ///
+    /// Opcodes that can't be decoded are printed as raw data.
+    pub fn disassemble_at(&self, pc: usize) -> String {
+        match self.instruction_at(pc) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => format!("DW #${:04x}", self.opcode_at(pc)),
+        }
+    }
+
+    /// The registers, timers and stack as text, with the next instruction.
+    ///
+    /// ```text
+    /// PC  0208  SE V2, #$01
+    /// I   0222
+    /// V0  00 00 01 00 00 00 00 00  V8  00 00 00 00 00 00 00 01
+    /// DT  00  ST  00
+    /// SP  1  0204
+    /// ```
+    pub fn dump_registers(&self) -> String {
+        let bytes = |v: &[u8]| {
+            v.iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let stack: String = self
+            .stack
+            .frames()
+            .iter()
+            .map(|addr| format!("  {:04X}", addr))
+            .collect();
+
+        format!(
+            "PC  {:04X}  {}\nI   {:04X}\nV0  {}  V8  {}\nDT  {:02X}  ST  {:02X}\nSP  {}{}\n",
+            self.pc,
+            self.disassemble_at(self.pc),
+            self.I,
+            bytes(&self.V[..8]),
+            bytes(&self.V[8..16]),
+            self.delay,
+            self.sound,
+            self.stack.depth(),
+            stack
+        )
+    }
+}
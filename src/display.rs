@@ -0,0 +1,243 @@
+/// Size of the screen in low resolution, the only mode of the original CHIP-8.
+pub const W: usize = 64;
+pub const H: usize = 32;
+
+/// Size of the screen in the SUPER-CHIP high resolution mode.
+pub const HIRES_W: usize = 128;
+pub const HIRES_H: usize = 64;
+
+/// Colors of the 4 pixel values: off, plane 1, plane 2 and both planes.
+///
+/// Only the first two are used by CHIP-8 and SUPER-CHIP ROMs.
+pub type Palette = [u32; 4];
+
+pub const DEFAULT_PALETTE: Palette = [0x000000, 0xffffff, 0xaaaaaa, 0x555555];
+
+/// The screen, its size depends on the resolution mode.
+///
+/// Every pixel has one bit per bitplane, XO-CHIP ROMs can draw on two planes to get
+/// 4 colors. The pixels are also kept rendered through the palette, ready to be shown.
+#[derive(Clone, Debug)]
+pub struct Display {
+    hires: bool,
+    /// Bitmask of the planes that drawing, clearing and scrolling act on.
+    selected_planes: u8,
+    /// One plane bitmask per pixel, row by row.
+    planes: Vec<u8>,
+    palette: Palette,
+    /// `width() * height()` pixels in `0RGB` format, row by row.
+    pixels: Vec<u32>,
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            hires: false,
+            selected_planes: 1,
+            planes: vec![0; W * H],
+            palette: DEFAULT_PALETTE,
+            pixels: vec![DEFAULT_PALETTE[0]; W * H],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_W
+        } else {
+            W
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_H
+        } else {
+            H
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// The rendered screen.
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    /// The raw pixel values, `0..=3`, before going through the palette.
+    pub fn planes(&self) -> &[u8] {
+        &self.planes
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.render();
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    /// Selects the planes affected by the next drawing operations (XO-CHIP `Fn01`).
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & 0b11;
+    }
+
+    /// How many of the planes are selected, sprites hold data for each one of them.
+    pub fn selected_plane_count(&self) -> usize {
+        self.selected_planes.count_ones() as usize
+    }
+
+    fn render(&mut self) {
+        let palette = self.palette;
+        for (out, value) in self.pixels.iter_mut().zip(self.planes.iter()) {
+            *out = palette[*value as usize];
+        }
+    }
+
+    /// Clears the selected planes.
+    pub fn clear(&mut self) {
+        let keep = !self.selected_planes;
+        for i in self.planes.iter_mut() {
+            *i &= keep;
+        }
+
+        self.render();
+    }
+
+    /// Switches between 64x32 and 128x64, the screen is cleared.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = vec![0; self.width() * self.height()];
+        self.pixels = vec![self.palette[0]; self.width() * self.height()];
+    }
+
+    /// Puts back a saved screen, returns `false` if `planes` doesn't have the size of
+    /// the resolution.
+    pub(crate) fn restore(&mut self, hires: bool, selected_planes: u8, planes: Vec<u8>) -> bool {
+        self.set_hires(hires);
+        if planes.len() != self.planes.len() {
+            return false;
+        }
+
+        self.select_planes(selected_planes);
+        self.planes = planes.into_iter().map(|pixel| pixel & 0b11).collect();
+        self.render();
+
+        true
+    }
+
+    /// Moves the selected planes, `dx` pixels right and `dy` pixels down. Pixels
+    /// scrolled in from the edges are blank.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (w, h) = (self.width() as isize, self.height() as isize);
+        let mask = self.selected_planes;
+        let old = self.planes.clone();
+
+        for y in 0..h {
+            for x in 0..w {
+                let (sx, sy) = (x - dx, y - dy);
+                let moved = if sx >= 0 && sx < w && sy >= 0 && sy < h {
+                    old[(sy * w + sx) as usize] & mask
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.planes[(y * w + x) as usize];
+                *pixel = (*pixel & !mask) | moved;
+            }
+        }
+
+        self.render();
+    }
+
+    /// Moves the picture `n` rows down, the top rows are blanked.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Moves the picture `n` rows up, the bottom rows are blanked (XO-CHIP).
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Moves every row `n` pixels to the right.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Moves every row `n` pixels to the left.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    /// XORs a sprite onto the selected planes at (`x`, `y`). `data` holds one byte per
+    /// row, or two when `wide` (16 pixels) is set, for each selected plane in turn.
+    ///
+    /// The starting position always wraps, only the pixels that fall off the edge are
+    /// clipped when `clip` is set. Returns how many rows turned at least one pixel off.
+    pub fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        data: &[u8],
+        wide: bool,
+        clip: bool,
+    ) -> usize {
+        let (w, h) = (self.width(), self.height());
+        let (x, y) = (x % w, y % h);
+
+        let bytes_per_row = if wide { 2 } else { 1 };
+        let plane_count = self.selected_plane_count().max(1);
+        let plane_len = data.len() / plane_count;
+
+        let mut collided_rows = vec![false; plane_len.div_ceil(bytes_per_row)];
+
+        let mask = self.selected_planes;
+        let selected = (0..2).map(|p| 1u8 << p).filter(|bit| mask & bit != 0);
+
+        for (plane, sprite) in selected.zip(data.chunks(plane_len.max(1))) {
+            for (i, row) in sprite.chunks(bytes_per_row).enumerate() {
+                let ii = y + i;
+                if ii >= h && clip {
+                    break;
+                }
+
+                let bits = row.iter().fold(0u16, |bits, b| (bits << 8) | *b as u16);
+                let width = 8 * row.len();
+
+                for j in 0..width {
+                    if bits & (1 << (width - 1 - j)) == 0 {
+                        continue;
+                    }
+
+                    let jj = x + j;
+                    if jj >= w && clip {
+                        break;
+                    }
+
+                    let index = (ii % h) * w + (jj % w);
+                    let pixel = &mut self.planes[index];
+
+                    collided_rows[i] |= *pixel & plane != 0;
+                    *pixel ^= plane;
+
+                    self.pixels[index] = self.palette[*pixel as usize];
+                }
+            }
+        }
+
+        collided_rows.iter().filter(|c| **c).count()
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
+}
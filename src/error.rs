@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::fmt;
+
+/// Everything that can go wrong while executing a ROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode at `addr` isn't an instruction of the interpreter.
+    UnknownOpcode { addr: usize, opcode: u16 },
+    /// A `CALL` at `addr` with a full stack.
+    StackOverflow { addr: usize },
+    /// A `RET` at `addr` with an empty stack.
+    StackUnderflow { addr: usize },
+    /// An instruction tried to access memory past the end of the address space.
+    MemoryOutOfBounds { addr: usize },
+    /// The program counter left the address space.
+    PcOutOfBounds { pc: usize },
+    /// The ROM doesn't fit in the program area, which is `max` bytes long.
+    RomTooLarge { size: usize, max: usize },
+    /// The configured memory is `size` bytes, too small for the fonts and the area
+    /// below `0x200`, which need `min` bytes.
+    MemoryTooSmall { size: usize, min: usize },
+    /// The fonts at `start..end` run into the program area at `0x200`.
+    FontOverlapsProgram { start: usize, end: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::UnknownOpcode { addr, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, addr)
+            }
+            Chip8Error::StackOverflow { addr } => write!(f, "stack overflow at {:03X}", addr),
+            Chip8Error::StackUnderflow { addr } => write!(f, "stack underflow at {:03X}", addr),
+            Chip8Error::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:03X}", addr)
+            }
+            Chip8Error::PcOutOfBounds { pc } => {
+                write!(f, "program counter out of bounds: {:03X}", pc)
+            }
+            Chip8Error::RomTooLarge { size, max } => write!(
+                f,
+                "ROM is {} bytes long but the program area only holds {} bytes",
+                size, max
+            ),
+            Chip8Error::MemoryTooSmall { size, min } => write!(
+                f,
+                "memory is {} bytes long but at least {} bytes are needed",
+                size, min
+            ),
+            Chip8Error::FontOverlapsProgram { start, end } => write!(
+                f,
+                "the fonts at {:03X}..{:03X} overlap the program area",
+                start, end
+            ),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
@@ -0,0 +1,587 @@
+use crate::cpu::CpuState;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// An expression on the state of the machine, for conditional breakpoints.
+///
+/// ```text
+/// V3 == 0x10 && I > 0x300
+/// mem[0x2F0] != 0
+/// sp > 2 || (dt == 0 && mem[I + 1] & 0x80)
+/// ```
+///
+/// The names are `V0` to `VF`, `I`, `pc`, `dt` and `st` (the timers), `sp` (the depth of
+/// the stack) and `mem[addr]` (a byte of memory), in any case. Numbers are decimal or
+/// hex with `0x`. The operators are the ones of C, with the same precedence:
+/// `! ~ -`, `* / %`, `+ -`, `<< >>`, `< <= > >=`, `== !=`, `&`, `^`, `|`, `&&` and `||`.
+///
+/// Values are 64 bit signed integers, comparisons give 1 or 0 and anything but 0 is
+/// true. Dividing by 0 gives 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expr {
+    text: String,
+    node: Node,
+}
+
+/// A syntax error in an expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExprError {
+    /// 1-based position of the error in the text.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl Error for ExprError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(usize),
+    I,
+    Pc,
+    Delay,
+    Sound,
+    Depth,
+    Memory(Box<Node>),
+    Unary(Unary, Box<Node>),
+    Binary(Binary, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Unary {
+    Not,
+    Complement,
+    Negate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Binary {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// The binary operators from the loosest to the tightest, the operators of a level
+/// have the same precedence.
+const PRECEDENCE: [&[(&str, Binary)]; 10] = [
+    &[("||", Binary::Or)],
+    &[("&&", Binary::And)],
+    &[("|", Binary::BitOr)],
+    &[("^", Binary::BitXor)],
+    &[("&", Binary::BitAnd)],
+    &[("==", Binary::Eq), ("!=", Binary::Ne)],
+    &[
+        ("<=", Binary::Le),
+        (">=", Binary::Ge),
+        ("<", Binary::Lt),
+        (">", Binary::Gt),
+    ],
+    &[("<<", Binary::Shl), (">>", Binary::Shr)],
+    &[("+", Binary::Add), ("-", Binary::Sub)],
+    &[("*", Binary::Mul), ("/", Binary::Div), ("%", Binary::Rem)],
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    /// An operator or a bracket.
+    Symbol(&'static str),
+}
+
+/// The symbols, the longest first so `<=` isn't read as `<` and `=`.
+const SYMBOLS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]",
+];
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            next: 0,
+            end: text.len(),
+        };
+
+        let node = parser.expression(0)?;
+        if let Some((column, token)) = parser.tokens.get(parser.next) {
+            return Err(ExprError {
+                column: column + 1,
+                message: format!("unexpected {}", describe(token)),
+            });
+        }
+
+        Ok(Expr {
+            text: text.trim().to_string(),
+            node,
+        })
+    }
+
+    /// The value of the expression for the machine in `cpu`.
+    pub fn eval(&self, cpu: &CpuState) -> i64 {
+        eval(&self.node, cpu)
+    }
+
+    /// Whether the expression isn't 0.
+    pub fn is_true(&self, cpu: &CpuState) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn eval(node: &Node, cpu: &CpuState) -> i64 {
+    match node {
+        Node::Number(n) => *n,
+        Node::Register(x) => cpu.V[*x] as i64,
+        Node::I => cpu.I as i64,
+        Node::Pc => cpu.pc as i64,
+        Node::Delay => cpu.delay as i64,
+        Node::Sound => cpu.sound as i64,
+        Node::Depth => cpu.stack.depth() as i64,
+        Node::Memory(addr) => {
+            let addr = eval(addr, cpu).rem_euclid(cpu.mem.len() as i64);
+            cpu.read(addr as usize) as i64
+        }
+        Node::Unary(op, value) => {
+            let value = eval(value, cpu);
+            match op {
+                Unary::Not => (value == 0) as i64,
+                Unary::Complement => !value,
+                Unary::Negate => value.wrapping_neg(),
+            }
+        }
+        // Short-circuits like in C.
+        Node::Binary(Binary::And, a, b) => (eval(a, cpu) != 0 && eval(b, cpu) != 0) as i64,
+        Node::Binary(Binary::Or, a, b) => (eval(a, cpu) != 0 || eval(b, cpu) != 0) as i64,
+        Node::Binary(op, a, b) => {
+            let (a, b) = (eval(a, cpu), eval(b, cpu));
+            let shift = u32::try_from(b).unwrap_or(u32::MAX);
+
+            match op {
+                Binary::Mul => a.wrapping_mul(b),
+                Binary::Div => a.checked_div(b).unwrap_or(0),
+                Binary::Rem => a.checked_rem(b).unwrap_or(0),
+                Binary::Add => a.wrapping_add(b),
+                Binary::Sub => a.wrapping_sub(b),
+                Binary::Shl => a.checked_shl(shift).unwrap_or(0),
+                Binary::Shr => a.checked_shr(shift).unwrap_or(0),
+                Binary::Lt => (a < b) as i64,
+                Binary::Le => (a <= b) as i64,
+                Binary::Gt => (a > b) as i64,
+                Binary::Ge => (a >= b) as i64,
+                Binary::Eq => (a == b) as i64,
+                Binary::Ne => (a != b) as i64,
+                Binary::BitAnd => a & b,
+                Binary::BitXor => a ^ b,
+                Binary::BitOr => a | b,
+                Binary::And | Binary::Or => unreachable!("handled above"),
+            }
+        }
+    }
+}
+
+/// Splits `text` into tokens, with their 0-based position.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap_or(' ');
+
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+
+            let token = if c.is_ascii_digit() {
+                let number = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                let number = number.map_err(|_| ExprError {
+                    column: pos + 1,
+                    message: format!("`{}` is not a number", word),
+                })?;
+                Token::Number(number)
+            } else {
+                Token::Name(word.to_lowercase())
+            };
+
+            tokens.push((pos, token));
+            pos += len;
+            continue;
+        }
+
+        let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol));
+        match symbol {
+            Some(symbol) => {
+                tokens.push((pos, Token::Symbol(symbol)));
+                pos += symbol.len();
+            }
+            None => {
+                return Err(ExprError {
+                    column: pos + 1,
+                    message: format!("unexpected `{}`", c),
+                })
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => format!("number {}", n),
+        Token::Name(name) => format!("`{}`", name),
+        Token::Symbol(symbol) => format!("`{}`", symbol),
+    }
+}
+
+/// A recursive descent parser, one function call per precedence level.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Length of the text, where errors at the end are reported.
+    end: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, message: String) -> Result<T, ExprError> {
+        let column = self.tokens.get(self.next).map_or(self.end, |(pos, _)| *pos);
+
+        Err(ExprError {
+            column: column + 1,
+            message,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    /// Consumes the next token if it's `symbol`.
+    fn eat(&mut self, symbol: &'static str) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.next += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ExprError> {
+        if self.eat(symbol) {
+            return Ok(());
+        }
+
+        match self.peek() {
+            Some(token) => self.error(format!("expected `{}`, got {}", symbol, describe(token))),
+            None => self.error(format!("expected `{}`", symbol)),
+        }
+    }
+
+    /// Parses the operators of `PRECEDENCE[level]` and tighter, left to right.
+    fn expression(&mut self, level: usize) -> Result<Node, ExprError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut node = self.expression(level + 1)?;
+
+        'operators: loop {
+            for (symbol, op) in PRECEDENCE[level].iter() {
+                if self.eat(symbol) {
+                    let right = self.expression(level + 1)?;
+                    node = Node::Binary(*op, Box::new(node), Box::new(right));
+                    continue 'operators;
+                }
+            }
+
+            return Ok(node);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        let op = if self.eat("!") {
+            Unary::Not
+        } else if self.eat("~") {
+            Unary::Complement
+        } else if self.eat("-") {
+            Unary::Negate
+        } else {
+            return self.primary();
+        };
+
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return self.error("expected a value".to_string()),
+        };
+
+        if self.eat("(") {
+            let node = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+
+        let name = match token {
+            Token::Number(n) => {
+                self.next += 1;
+                return Ok(Node::Number(n));
+            }
+            Token::Name(name) => name,
+            Token::Symbol(_) => return self.error(format!("unexpected {}", describe(&token))),
+        };
+
+        let node = match name.as_str() {
+            "i" => Node::I,
+            "pc" => Node::Pc,
+            "dt" => Node::Delay,
+            "st" => Node::Sound,
+            "sp" => Node::Depth,
+            "mem" => {
+                self.next += 1;
+                self.expect("[")?;
+                let addr = self.expression(0)?;
+                self.expect("]")?;
+                return Ok(Node::Memory(Box::new(addr)));
+            }
+            _ => match name.strip_prefix('v').map(|x| usize::from_str_radix(x, 16)) {
+                Some(Ok(x)) if name.len() == 2 => Node::Register(x),
+                _ => return self.error(format!("unknown name `{}`", name)),
+            },
+        };
+        self.next += 1;
+
+        Ok(node)
+    }
+}
+
+/// A message with expressions between braces, for tracepoints.
+///
+/// ```text
+/// score {mem[0x2F0]}, x = {V3}, I = {I:x}
+/// ```
+///
+/// `{expr}` prints the value in decimal and `{expr:x}` in hex. `{{` and `}}` print
+/// braces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Value { expr: Expr, hex: bool },
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Template, ExprError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((pos, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let len = text[pos..].find('}').ok_or_else(|| ExprError {
+                        column: pos + 1,
+                        message: "`{` without `}`".to_string(),
+                    })?;
+                    let inside = &text[pos + 1..pos + len];
+                    let (source, hex) = match inside.strip_suffix(":x") {
+                        Some(source) => (source, true),
+                        None => (inside, false),
+                    };
+
+                    // The errors are relative to the expression, move them to the text.
+                    let expr = Expr::parse(source).map_err(|e| ExprError {
+                        column: e.column + pos + 1,
+                        message: e.message,
+                    })?;
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Value { expr, hex });
+
+                    while chars.peek().is_some_and(|(p, _)| *p <= pos + len) {
+                        chars.next();
+                    }
+                }
+                '}' => {
+                    return Err(ExprError {
+                        column: pos + 1,
+                        message: "`}` without `{`".to_string(),
+                    })
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// The message, with the values for the machine in `cpu`.
+    pub fn render(&self, cpu: &CpuState) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value { expr, hex: true } => format!("{:X}", expr.eval(cpu)),
+                Part::Value { expr, hex: false } => expr.eval(cpu).to_string(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn cpu() -> CpuState {
+        let mut cpu = CpuState::new(&[0x60, 0x05, 0x12, 0x02], &Config::default()).unwrap();
+        cpu.V[3] = 0x10;
+        cpu.V[0xF] = 1;
+        cpu.I = 0x300;
+        cpu.mem[0x300] = 0xAB;
+        cpu.delay = 30;
+
+        cpu
+    }
+
+    fn eval(text: &str) -> i64 {
+        Expr::parse(text).unwrap().eval(&cpu())
+    }
+
+    fn error(text: &str) -> ExprError {
+        Expr::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("1 << 4 | 1"), 17);
+        assert_eq!(eval("6 & 3 ^ 1"), 3);
+        assert_eq!(eval("-2 * -3 + ~0"), 5);
+        assert_eq!(eval("0x10 % 7 + 100 / 9"), 13);
+        assert_eq!(eval("7 / 0 + 7 % 0"), 0);
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        assert_eq!(eval("3 < 4 == 1"), 1);
+        assert_eq!(eval("4 <= 4 && 5 >= 6"), 0);
+        assert_eq!(eval("1 != 1 || 2 > 1"), 1);
+        assert_eq!(eval("!5 + !0"), 1);
+        assert_eq!(eval("0 && 1 / 0"), 0);
+        assert_eq!(eval("1 || 0"), 1);
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        assert_eq!(eval("V3 == 0x10 && I > 0x2FF"), 1);
+        assert_eq!(eval("vf + v3"), 0x11);
+        assert_eq!(eval("pc"), 0x200);
+        assert_eq!(eval("dt + st + sp"), 30);
+        assert_eq!(eval("mem[I]"), 0xAB);
+        assert_eq!(eval("MEM[0x200] == 0x60 && mem[pc + 1] == 5"), 1);
+        // Addresses wrap around like the memory accesses of the machine.
+        assert_eq!(eval("mem[0x1300]"), 0xAB);
+        assert_eq!(eval("mem[-1]"), 0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            ("", 1, "expected a value"),
+            ("1 +", 4, "expected a value"),
+            ("(1", 3, "expected `)`"),
+            ("v10", 1, "unknown name `v10`"),
+            ("V3 == foo", 7, "unknown name `foo`"),
+            ("1 @ 2", 3, "unexpected `@`"),
+            ("mem 1", 5, "expected `[`, got number 1"),
+            ("0xZZ", 1, "`0xZZ` is not a number"),
+            ("1 2", 3, "unexpected number 2"),
+        ];
+
+        for (text, column, message) in cases.iter() {
+            let e = error(text);
+            assert_eq!(
+                (e.column, e.message.as_str()),
+                (*column, *message),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn templates() {
+        let cpu = cpu();
+        let render = |text: &str| Template::parse(text).unwrap().render(&cpu);
+
+        assert_eq!(render("V3={V3} I={I:x}"), "V3=16 I=300");
+        assert_eq!(render("{mem[I]:x}{{}}"), "AB{}");
+        assert_eq!(render("no values"), "no values");
+
+        assert_eq!(Template::parse("{").unwrap_err().column, 1);
+        assert_eq!(Template::parse("a }").unwrap_err().column, 3);
+        assert_eq!(Template::parse("ab {1 +}").unwrap_err().column, 8);
+        assert_eq!(Template::parse("ab {nope}").unwrap_err().column, 5);
+    }
+}
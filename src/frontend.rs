@@ -0,0 +1,344 @@
+use chip8::{
+    Chip8, Debugger, FrameLimiter, Keymap, Rewind, SaveSlots, StopReason, FRAME_RATE, HIRES_H,
+    HIRES_W,
+};
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Speed limits for the `-`/`=` hotkeys, in instructions per frame.
+const MIN_SPEED: usize = 1;
+const MAX_SPEED: usize = 10_000;
+
+/// The save state slots, F1 to F9 load them and Shift + F1 to F9 save them.
+const SLOT_KEYS: [Key; 9] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+];
+
+/// Toggles the pause. While paused Space steps one instruction, Enter steps over a
+/// `CALL` and Shift + Enter steps out of the subroutine.
+const PAUSE_KEY: Key = Key::F12;
+const STEP_KEY: Key = Key::Space;
+const STEP_OVER_KEY: Key = Key::Enter;
+
+/// Saves a screenshot, see [`Settings::screenshots`].
+const SCREENSHOT_KEY: Key = Key::F10;
+
+/// Starts recording a GIF next to the screenshots, or stops the recording.
+const RECORD_KEY: Key = Key::F11;
+
+/// How the window is set up.
+pub struct Settings {
+    /// Size of a SUPER-CHIP pixel, 1, 2, 4, 8, 16 or 32.
+    pub scale: usize,
+    pub keymap: Keymap,
+    pub slots: Option<SaveSlots>,
+    /// Paused with `--paused`, and with the breakpoints of `--break`.
+    pub debugger: Debugger,
+    /// The directory of the screenshots and recordings.
+    pub screenshots: PathBuf,
+    /// Image pixels per screen pixel of the screenshots and recordings.
+    pub screenshot_scale: usize,
+    /// The file name of the ROM, screenshots are named after it.
+    pub rom_name: String,
+    /// How far back Backspace can rewind, 0 turns rewinding off.
+    pub rewind_seconds: usize,
+}
+
+/// The name of `key` in keymaps, `1`, `Q`, `Semicolon`...
+fn key_name(key: Key) -> String {
+    let name = format!("{:?}", key);
+
+    // The digits are `Key0` to `Key9`.
+    match name.strip_prefix("Key") {
+        Some(digit) => digit.to_string(),
+        None => name,
+    }
+}
+
+fn window_scale(scale: usize) -> Scale {
+    match scale {
+        1 => Scale::X1,
+        2 => Scale::X2,
+        4 => Scale::X4,
+        16 => Scale::X16,
+        32 => Scale::X32,
+        _ => Scale::X8,
+    }
+}
+
+/// Copies the screen into the window buffer, low resolution pixels are doubled so the
+/// window can keep the size of the SUPER-CHIP high resolution mode.
+fn present(chip8: &Chip8, buffer: &mut [u32]) {
+    let (w, h) = chip8.screen_size();
+    let (sx, sy) = (HIRES_W / w, HIRES_H / h);
+    let pixels = chip8.framebuffer();
+
+    for (i, out) in buffer.iter_mut().enumerate() {
+        let (x, y) = (i % HIRES_W, i / HIRES_W);
+        *out = pixels[(y / sy) * w + x / sx];
+    }
+}
+
+/// The date and time in UTC as `YYYYMMDD-HHMMSS`.
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    // Howard Hinnant's `civil_from_days`, with eras of 400 years starting on March 1st.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// A new file in `dir` named after the ROM and the time, `pong-20240131-235959.png`.
+fn capture_path(dir: &Path, rom_name: &str, extension: &str) -> PathBuf {
+    let stem = rom_name.rsplit_once('.').map_or(rom_name, |(stem, _)| stem);
+    let name = format!("{}-{}", stem, timestamp());
+
+    // Several screenshots can be taken in the same second.
+    let mut path = dir.join(format!("{}.{}", name, extension));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", name, n, extension));
+        n += 1;
+    }
+
+    path
+}
+
+fn save_screenshot(chip8: &Chip8, dir: &Path, rom_name: &str, scale: usize) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let path = capture_path(dir, rom_name, "png");
+    let mut file = BufWriter::new(File::create(&path)?);
+    chip8.write_screenshot(&mut file, scale)?;
+    file.flush()?;
+
+    Ok(path)
+}
+
+/// Stops the recording if there is one, or starts recording a GIF.
+fn toggle_recording(chip8: &mut Chip8, dir: &Path, rom_name: &str, scale: usize) {
+    if let Some(mut recorder) = chip8.remove_video_sink() {
+        match recorder.finish() {
+            Ok(()) => println!("recording stopped"),
+            Err(e) => eprintln!("recording: {}", e),
+        }
+        return;
+    }
+
+    let path = capture_path(dir, rom_name, "gif");
+    let recorder = fs::create_dir_all(dir).and_then(|()| crate::create_recorder(&path, scale));
+    match recorder {
+        Ok(recorder) => {
+            chip8.set_video_sink(recorder);
+            println!("recording {}", path.display());
+        }
+        Err(e) => eprintln!("recording: {}", e),
+    }
+}
+
+/// Prints why the machine paused, and where it is.
+fn print_stop(reason: Option<StopReason>, debugger: &Debugger, chip8: &Chip8) {
+    match reason {
+        Some(StopReason::Error(e)) => eprintln!("{}", e),
+        Some(StopReason::Done) | None => {}
+        Some(reason) => println!("{}", crate::describe_stop(&reason, chip8)),
+    }
+
+    let cpu = chip8.cpu();
+    print!(
+        "{}{}Memory at PC\n{}Memory at I\n{}",
+        debugger.disassembly_view(chip8, 7),
+        debugger.registers_view(chip8),
+        debugger.memory_view(chip8, cpu.pc, 2),
+        debugger.memory_view(chip8, cpu.I as usize, 2)
+    );
+}
+
+fn window_title(chip8: &Chip8, paused: bool) -> String {
+    let paused = if paused { " (paused)" } else { "" };
+
+    format!("CHIP-8 - {} instructions/frame{}", chip8.speed(), paused)
+}
+
+/// Plays `chip8` in a window until it's closed or Escape is pressed.
+pub fn run(mut chip8: Chip8, settings: Settings) -> io::Result<()> {
+    let Settings {
+        scale,
+        keymap,
+        slots,
+        mut debugger,
+        screenshots,
+        screenshot_scale,
+        rom_name,
+        rewind_seconds,
+    } = settings;
+
+    let mut window = Window::new(
+        "CHIP-8",
+        HIRES_W,
+        HIRES_H,
+        WindowOptions {
+            resize: false,
+            scale: window_scale(scale),
+            ..WindowOptions::default()
+        },
+    )
+    .map_err(|e| io::Error::other(format!("can't open the window: {:?}", e)))?;
+
+    let mut paused = debugger.is_paused();
+    window.set_title(&window_title(&chip8, paused));
+    if paused {
+        print_stop(None, &debugger, &chip8);
+    }
+
+    #[cfg(feature = "audio")]
+    match chip8::Speaker::new() {
+        Ok(speaker) => chip8.set_audio_sink(Box::new(speaker)),
+        Err(e) => eprintln!("no sound: {}", e),
+    }
+
+    let mut rewind = Rewind::with_seconds(rewind_seconds);
+    let mut limiter = FrameLimiter::new(FRAME_RATE);
+    let mut buffer = vec![0; HIRES_W * HIRES_H];
+
+    while window.is_open() {
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+
+        if window.is_key_pressed(PAUSE_KEY, KeyRepeat::No) {
+            debugger.toggle_pause();
+            if debugger.is_paused() {
+                print_stop(None, &debugger, &chip8);
+            }
+        }
+
+        if debugger.is_paused() {
+            if window.is_key_pressed(STEP_KEY, KeyRepeat::Yes) {
+                debugger.step();
+            } else if window.is_key_pressed(STEP_OVER_KEY, KeyRepeat::Yes) {
+                if !shift {
+                    debugger.step_over(&chip8);
+                } else if !debugger.step_out(&chip8) {
+                    println!("not in a subroutine");
+                }
+            }
+        }
+
+        if window.is_key_pressed(SCREENSHOT_KEY, KeyRepeat::No) {
+            match save_screenshot(&chip8, &screenshots, &rom_name, screenshot_scale) {
+                Ok(path) => println!("saved {}", path.display()),
+                Err(e) => eprintln!("screenshot: {}", e),
+            }
+        }
+
+        if window.is_key_pressed(RECORD_KEY, KeyRepeat::No) {
+            toggle_recording(&mut chip8, &screenshots, &rom_name, screenshot_scale);
+        }
+
+        if window.is_key_down(Key::Escape) {
+            break;
+        }
+
+        if let Some(slots) = slots.as_ref() {
+            for (i, key) in SLOT_KEYS.iter().enumerate() {
+                if !window.is_key_pressed(*key, KeyRepeat::No) {
+                    continue;
+                }
+
+                let slot = i as u8 + 1;
+                let result = if shift {
+                    slots.save(slot, &chip8)
+                } else {
+                    slots.load(slot, &mut chip8)
+                };
+
+                match result {
+                    Ok(()) if shift => println!("saved slot {}", slot),
+                    Ok(()) => println!("loaded slot {}", slot),
+                    Err(e) => eprintln!("slot {}: {}", slot, e),
+                }
+            }
+        }
+
+        if window.is_key_pressed(Key::Minus, KeyRepeat::Yes) {
+            chip8.set_speed((chip8.speed() / 2).max(MIN_SPEED));
+            window.set_title(&window_title(&chip8, paused));
+        }
+
+        if window.is_key_pressed(Key::Equal, KeyRepeat::Yes) {
+            chip8.set_speed((chip8.speed() * 2).min(MAX_SPEED));
+            window.set_title(&window_title(&chip8, paused));
+        }
+
+        // Only the keys that changed are reported, so presses aren't repeated.
+        let mut held = [false; 16];
+        if let Some(keys) = window.get_keys() {
+            for t in keys {
+                if let Some(k) = keymap.get(&key_name(t)) {
+                    held[k as usize] = true;
+                }
+            }
+        }
+        for (key, pressed) in held.iter().enumerate() {
+            chip8.set_key(key as u8, *pressed);
+        }
+
+        // Holding Backspace plays the game backwards, one frame per frame.
+        if window.is_key_down(Key::Backspace) {
+            rewind.rewind(&mut chip8);
+        } else if !debugger.is_paused() {
+            let stop = debugger.run_frame(&mut chip8);
+            for entry in debugger.take_log() {
+                println!("{}", crate::describe_log(&entry, &chip8));
+            }
+            if let Some(reason) = stop {
+                print_stop(Some(reason), &debugger, &chip8);
+            }
+            rewind.push(&chip8);
+        }
+
+        if debugger.is_paused() != paused {
+            paused = debugger.is_paused();
+            window.set_title(&window_title(&chip8, paused));
+        }
+
+        present(&chip8, &mut buffer);
+        window
+            .update_with_buffer(&buffer)
+            .map_err(|e| io::Error::other(format!("can't draw the window: {:?}", e)))?;
+
+        limiter.wait();
+    }
+
+    crate::stop_recording(&mut chip8)
+}
@@ -0,0 +1,297 @@
+use crate::display::Display;
+use std::io::{self, Write};
+
+/// Characters of [`Display::to_ascii`] for the 4 pixel values.
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '*'];
+
+/// The longest block of a zlib stream stored without compression.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+impl Display {
+    /// Writes the screen as an 8 bit RGB PNG, one image pixel per screen pixel.
+    pub fn write_png<W: Write>(&self, out: W) -> io::Result<()> {
+        self.write_png_scaled(out, 1)
+    }
+
+    /// Writes the screen as an 8 bit RGB PNG in the colors of the palette, every screen
+    /// pixel is a `scale * scale` square.
+    pub fn write_png_scaled<W: Write>(&self, mut out: W, scale: usize) -> io::Result<()> {
+        let scale = scale.max(1);
+        let (w, h) = (self.width() * scale, self.height() * scale);
+
+        // Every row starts with its filter type, 0 is no filter.
+        let mut raw = Vec::with_capacity((w * 3 + 1) * h);
+        for row in self.pixels().chunks(self.width()) {
+            let start = raw.len();
+            raw.push(0);
+            for pixel in row {
+                for _ in 0..scale {
+                    raw.extend_from_slice(&pixel.to_be_bytes()[1..]);
+                }
+            }
+            for _ in 1..scale {
+                raw.extend_from_within(start..start + w * 3 + 1);
+            }
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(w as u32).to_be_bytes());
+        header.extend_from_slice(&(h as u32).to_be_bytes());
+        // Bit depth 8, color type 2 (RGB), default compression, filters and no interlace.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_chunk(&mut out, b"IHDR", &header)?;
+        write_chunk(&mut out, b"IDAT", &zlib_stored(&raw))?;
+        write_chunk(&mut out, b"IEND", &[])
+    }
+
+    /// Writes the screen as a binary PBM, the pixels lit on any plane are black.
+    pub fn write_pbm<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let (w, h) = (self.width(), self.height());
+        let mut data = Vec::with_capacity(w.div_ceil(8) * h);
+
+        for row in self.planes().chunks(w) {
+            for byte in row.chunks(8) {
+                let bits = byte
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, planes)| **planes != 0)
+                    .fold(0u8, |bits, (i, _)| bits | (0x80 >> i));
+                data.push(bits);
+            }
+        }
+
+        write!(out, "P4\n{} {}\n", w, h)?;
+        out.write_all(&data)
+    }
+
+    /// The screen as text, one line per row: `.` for the pixels that are off and `#`
+    /// for the lit ones, `+` and `*` for XO-CHIP's second plane and both planes.
+    pub fn to_ascii(&self) -> String {
+        let w = self.width();
+        let mut text = String::with_capacity((w + 1) * self.height());
+
+        for row in self.planes().chunks(w) {
+            text.extend(row.iter().map(|planes| ASCII_PIXELS[*planes as usize & 3]));
+            text.push('\n');
+        }
+
+        text
+    }
+}
+
+/// Writes the chunk length, type, data and CRC of the type and data.
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let crc = crc32(crc32(!0, kind), data);
+    out.write_all(&(!crc).to_be_bytes())
+}
+
+/// A zlib stream made of stored blocks. Screens are tiny, compressing them isn't worth
+/// an implementation of deflate.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+
+    // Deflate with a 32 KiB window, and no preset dictionary.
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none() as u8;
+        let len = chunk.len() as u16;
+
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+
+    out
+}
+
+/// Updates a CRC-32 (the one of PNG and zip), start with `!0` and invert the result.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The blocks of a stream from [`zlib_stored`] as (final, data), and the checksum.
+    fn stored_blocks(stream: &[u8]) -> (Vec<(bool, &[u8])>, u32) {
+        assert_eq!(&stream[..2], &[0x78, 0x01]);
+
+        let (mut blocks, mut pos) = (Vec::new(), 2);
+        loop {
+            let last = stream[pos] == 1;
+            let len = u16::from_le_bytes([stream[pos + 1], stream[pos + 2]]);
+            let nlen = u16::from_le_bytes([stream[pos + 3], stream[pos + 4]]);
+            assert_eq!(len, !nlen);
+
+            let len = len as usize;
+            blocks.push((last, &stream[pos + 5..pos + 5 + len]));
+            pos += 5 + len;
+            if last {
+                break;
+            }
+        }
+
+        assert_eq!(pos + 4, stream.len());
+        let checksum = u32::from_be_bytes([
+            stream[pos],
+            stream[pos + 1],
+            stream[pos + 2],
+            stream[pos + 3],
+        ]);
+        (blocks, checksum)
+    }
+
+    fn inflate(blocks: &[(bool, &[u8])]) -> Vec<u8> {
+        blocks
+            .iter()
+            .flat_map(|(_, data)| data.iter().copied())
+            .collect()
+    }
+
+    /// A low resolution screen with a pixel on each plane and one on both.
+    fn planes() -> Display {
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, &[0x80], false, false);
+        display.select_planes(2);
+        display.draw_sprite(1, 0, &[0x80], false, false);
+        display.select_planes(3);
+        display.draw_sprite(9, 1, &[0x80, 0x80], false, false);
+        display
+    }
+
+    #[test]
+    fn pbm() {
+        let mut pbm = Vec::new();
+        planes().write_pbm(&mut pbm).unwrap();
+
+        let header = b"P4\n64 32\n";
+        assert_eq!(&pbm[..header.len()], header);
+
+        let rows = &pbm[header.len()..];
+        assert_eq!(rows.len(), 8 * 32);
+        assert_eq!(&rows[..8], &[0xc0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&rows[8..16], &[0, 0x40, 0, 0, 0, 0, 0, 0]);
+        assert!(rows[16..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn ascii() {
+        let text = planes().to_ascii();
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines.len(), 32);
+        assert!(lines.iter().all(|line| line.len() == 64));
+        assert!(text.ends_with('\n'));
+        assert_eq!(&lines[0][..4], "#+..");
+        assert_eq!(&lines[1][..12], ".........*..");
+        assert!(lines[2].chars().all(|c| c == '.'));
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(!crc32(!0, b"IEND"), 0xae42_6082);
+        assert_eq!(!crc32(!0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(!crc32(crc32(!0, b"1234"), b"56789"), 0xcbf4_3926);
+
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn stored_blocks_are_split_at_65535_bytes() {
+        let data: Vec<u8> = (0..2 * MAX_STORED_BLOCK + 1).map(|i| i as u8).collect();
+        let stream = zlib_stored(&data);
+        let (blocks, checksum) = stored_blocks(&stream);
+
+        let lens: Vec<(bool, usize)> = blocks.iter().map(|(l, b)| (*l, b.len())).collect();
+        assert_eq!(lens, [(false, 0xffff), (false, 0xffff), (true, 1)]);
+        assert_eq!(inflate(&blocks), data);
+        assert_eq!(checksum, adler32(&data));
+
+        let stream = zlib_stored(&data[..MAX_STORED_BLOCK]);
+        assert_eq!(stored_blocks(&stream).0.len(), 1);
+
+        let stream = zlib_stored(&[]);
+        assert_eq!(stored_blocks(&stream), (vec![(true, &[][..])], 1));
+    }
+
+    #[test]
+    fn scaled_png() {
+        let mut display = Display::new();
+        display.draw_sprite(0, 0, &[0x80], false, false);
+
+        let mut png = Vec::new();
+        display.write_png_scaled(&mut png, 3).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[8..16], b"\x00\x00\x00\x0dIHDR");
+        assert_eq!(
+            u32::from_be_bytes([png[16], png[17], png[18], png[19]]),
+            64 * 3
+        );
+        assert_eq!(
+            u32::from_be_bytes([png[20], png[21], png[22], png[23]]),
+            32 * 3
+        );
+        assert_eq!(&png[24..29], &[8, 2, 0, 0, 0]);
+        assert_eq!(&png[29..33], &(!crc32(!0, &png[12..29])).to_be_bytes());
+        assert_eq!(
+            &png[png.len() - 12..],
+            b"\x00\x00\x00\x00IEND\xae\x42\x60\x82"
+        );
+
+        let len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let (blocks, _) = stored_blocks(&png[41..41 + len]);
+        let raw = inflate(&blocks);
+
+        let stride = 1 + 64 * 3 * 3;
+        assert_eq!(raw.len(), stride * 32 * 3);
+        for row in raw.chunks(stride).take(3) {
+            assert_eq!(row[0], 0);
+            assert_eq!(&row[1..10], &[0xff; 9]);
+            assert_eq!(&row[10..13], &[0; 3]);
+        }
+        assert!(raw[3 * stride..]
+            .chunks(stride)
+            .all(|row| row[1..4] == [0; 3]));
+    }
+}
@@ -0,0 +1,409 @@
+use crate::config::Variant;
+use std::error::Error;
+use std::fmt;
+
+/// A decoded CHIP-8 instruction.
+///
+/// Registers are stored as their index (`0x0..=0xF`), addresses as 12 bit values
+/// and bytes as they appear in the opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00E0 - CLS
+    Cls,
+    /// 00EE - RET
+    Ret,
+    /// 00Cn - SCD nibble (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 00Dn - SCU nibble (XO-CHIP)
+    ScrollUp(u8),
+    /// 00FB - SCR (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC - SCL (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD - EXIT (SUPER-CHIP)
+    Exit,
+    /// 00FE - LOW (SUPER-CHIP)
+    LowRes,
+    /// 00FF - HIGH (SUPER-CHIP)
+    HighRes,
+    /// 1nnn - JP addr
+    Jump(u16),
+    /// 2nnn - CALL addr
+    Call(u16),
+    /// 3xkk - SE Vx, byte
+    SkipEqByte(u8, u8),
+    /// 4xkk - SNE Vx, byte
+    SkipNeqByte(u8, u8),
+    /// 5xy0 - SE Vx, Vy
+    SkipEqReg(u8, u8),
+    /// 5xy2 - SAVE Vx - Vy (XO-CHIP)
+    SaveRange(u8, u8),
+    /// 5xy3 - LOAD Vx - Vy (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 6xkk - LD Vx, byte
+    LoadByte(u8, u8),
+    /// 7xkk - ADD Vx, byte
+    AddByte(u8, u8),
+    /// 8xy0 - LD Vx, Vy
+    LoadReg(u8, u8),
+    /// 8xy1 - OR Vx, Vy
+    Or(u8, u8),
+    /// 8xy2 - AND Vx, Vy
+    And(u8, u8),
+    /// 8xy3 - XOR Vx, Vy
+    Xor(u8, u8),
+    /// 8xy4 - ADD Vx, Vy
+    AddReg(u8, u8),
+    /// 8xy5 - SUB Vx, Vy
+    Sub(u8, u8),
+    /// 8xy6 - SHR Vx, Vy
+    ShiftRight(u8, u8),
+    /// 8xy7 - SUBN Vx, Vy
+    SubN(u8, u8),
+    /// 8xyE - SHL Vx, Vy
+    ShiftLeft(u8, u8),
+    /// 9xy0 - SNE Vx, Vy
+    SkipNeqReg(u8, u8),
+    /// Annn - LD I, addr
+    LoadI(u16),
+    /// Bnnn - JP V0, addr
+    JumpV0(u16),
+    /// Cxkk - RND Vx, byte
+    Random(u8, u8),
+    /// Dxyn - DRW Vx, Vy, nibble
+    Draw(u8, u8, u8),
+    /// Ex9E - SKP Vx
+    SkipKey(u8),
+    /// ExA1 - SKNP Vx
+    SkipNoKey(u8),
+    /// F000 nnnn - LD I, long addr (XO-CHIP), the only 4 bytes instruction
+    LoadLongI(u16),
+    /// Fn01 - PLANE n (XO-CHIP)
+    Plane(u8),
+    /// F002 - AUDIO (XO-CHIP)
+    LoadAudio,
+    /// Fx07 - LD Vx, DT
+    LoadDelay(u8),
+    /// Fx0A - LD Vx, K
+    WaitKey(u8),
+    /// Fx15 - LD DT, Vx
+    SetDelay(u8),
+    /// Fx18 - LD ST, Vx
+    SetSound(u8),
+    /// Fx1E - ADD I, Vx
+    AddI(u8),
+    /// Fx29 - LD F, Vx
+    LoadFont(u8),
+    /// Fx30 - LD HF, Vx (SUPER-CHIP)
+    LoadBigFont(u8),
+    /// Fx3A - PITCH Vx (XO-CHIP)
+    SetPitch(u8),
+    /// Fx33 - LD B, Vx
+    StoreBcd(u8),
+    /// Fx55 - LD [I], Vx
+    StoreRegs(u8),
+    /// Fx65 - LD Vx, [I]
+    LoadRegs(u8),
+    /// Fx75 - LD R, Vx (SUPER-CHIP)
+    StoreFlags(u8),
+    /// Fx85 - LD Vx, R (SUPER-CHIP)
+    LoadFlags(u8),
+}
+
+/// Returned by [`Instruction::decode`] when the opcode isn't a known instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {:04X}", self.opcode)
+    }
+}
+
+impl Error for DecodeError {}
+
+impl Instruction {
+    /// Decodes the instruction starting with `opcode`, `next` is the word that follows
+    /// it in memory. Only the XO-CHIP `F000 nnnn` uses it.
+    pub fn decode_long(opcode: u16, next: u16) -> Result<Instruction, DecodeError> {
+        match opcode {
+            0xF000 => Ok(Instruction::LoadLongI(next)),
+            _ => Instruction::decode(opcode),
+        }
+    }
+
+    /// Decodes a big endian opcode, as it is stored in memory.
+    ///
+    /// `F000` needs the following word and can only be decoded by
+    /// [`Instruction::decode_long`].
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        let x = ((opcode & 0x0f00) >> 8) as u8;
+        let y = ((opcode & 0x00f0) >> 4) as u8;
+        let n = (opcode & 0x000f) as u8;
+        let kk = (opcode & 0x00ff) as u8;
+        let nnn = opcode & 0x0fff;
+
+        let instruction = match (opcode & 0xf000) >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
+                _ if opcode & 0xfff0 == 0x00C0 => Instruction::ScrollDown(n),
+                _ if opcode & 0xfff0 == 0x00D0 => Instruction::ScrollUp(n),
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipEqByte(x, kk),
+            0x4 => Instruction::SkipNeqByte(x, kk),
+            0x5 => match n {
+                0x0 => Instruction::SkipEqReg(x, y),
+                0x2 => Instruction::SaveRange(x, y),
+                0x3 => Instruction::LoadRange(x, y),
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x6 => Instruction::LoadByte(x, kk),
+            0x7 => Instruction::AddByte(x, kk),
+            0x8 => match n {
+                0x0 => Instruction::LoadReg(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddReg(x, y),
+                0x5 => Instruction::Sub(x, y),
+                0x6 => Instruction::ShiftRight(x, y),
+                0x7 => Instruction::SubN(x, y),
+                0xE => Instruction::ShiftLeft(x, y),
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x9 if n == 0 => Instruction::SkipNeqReg(x, y),
+            0xA => Instruction::LoadI(nnn),
+            0xB => Instruction::JumpV0(nnn),
+            0xC => Instruction::Random(x, kk),
+            0xD => Instruction::Draw(x, y, n),
+            0xE => match kk {
+                0x9E => Instruction::SkipKey(x),
+                0xA1 => Instruction::SkipNoKey(x),
+                _ => return Err(DecodeError { opcode }),
+            },
+            0xF => match kk {
+                0x01 => Instruction::Plane(x),
+                0x02 if x == 0 => Instruction::LoadAudio,
+                0x07 => Instruction::LoadDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::LoadFont(x),
+                0x30 => Instruction::LoadBigFont(x),
+                0x3A => Instruction::SetPitch(x),
+                0x33 => Instruction::StoreBcd(x),
+                0x55 => Instruction::StoreRegs(x),
+                0x65 => Instruction::LoadRegs(x),
+                0x75 => Instruction::StoreFlags(x),
+                0x85 => Instruction::LoadFlags(x),
+                _ => return Err(DecodeError { opcode }),
+            },
+            _ => return Err(DecodeError { opcode }),
+        };
+
+        Ok(instruction)
+    }
+
+    /// How many bytes the instruction takes in memory.
+    pub fn size(&self) -> usize {
+        match *self {
+            Instruction::LoadLongI(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// The inverse of [`Instruction::decode`].
+    ///
+    /// For `LoadLongI` this is only the first word, `F000`, the address follows it.
+    pub fn encode(&self) -> u16 {
+        fn xkk(high: u16, x: u8, kk: u8) -> u16 {
+            (high << 12) | ((x as u16 & 0xf) << 8) | kk as u16
+        }
+
+        fn xyn(high: u16, x: u8, y: u8, n: u8) -> u16 {
+            (high << 12) | ((x as u16 & 0xf) << 8) | ((y as u16 & 0xf) << 4) | (n as u16 & 0xf)
+        }
+
+        match *self {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xf),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xf),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump(nnn) => 0x1000 | (nnn & 0x0fff),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0x0fff),
+            Instruction::SkipEqByte(x, kk) => xkk(0x3, x, kk),
+            Instruction::SkipNeqByte(x, kk) => xkk(0x4, x, kk),
+            Instruction::SkipEqReg(x, y) => xyn(0x5, x, y, 0x0),
+            Instruction::SaveRange(x, y) => xyn(0x5, x, y, 0x2),
+            Instruction::LoadRange(x, y) => xyn(0x5, x, y, 0x3),
+            Instruction::LoadByte(x, kk) => xkk(0x6, x, kk),
+            Instruction::AddByte(x, kk) => xkk(0x7, x, kk),
+            Instruction::LoadReg(x, y) => xyn(0x8, x, y, 0x0),
+            Instruction::Or(x, y) => xyn(0x8, x, y, 0x1),
+            Instruction::And(x, y) => xyn(0x8, x, y, 0x2),
+            Instruction::Xor(x, y) => xyn(0x8, x, y, 0x3),
+            Instruction::AddReg(x, y) => xyn(0x8, x, y, 0x4),
+            Instruction::Sub(x, y) => xyn(0x8, x, y, 0x5),
+            Instruction::ShiftRight(x, y) => xyn(0x8, x, y, 0x6),
+            Instruction::SubN(x, y) => xyn(0x8, x, y, 0x7),
+            Instruction::ShiftLeft(x, y) => xyn(0x8, x, y, 0xE),
+            Instruction::SkipNeqReg(x, y) => xyn(0x9, x, y, 0x0),
+            Instruction::LoadI(nnn) => 0xA000 | (nnn & 0x0fff),
+            Instruction::JumpV0(nnn) => 0xB000 | (nnn & 0x0fff),
+            Instruction::Random(x, kk) => xkk(0xC, x, kk),
+            Instruction::Draw(x, y, n) => xyn(0xD, x, y, n),
+            Instruction::SkipKey(x) => xkk(0xE, x, 0x9E),
+            Instruction::SkipNoKey(x) => xkk(0xE, x, 0xA1),
+            Instruction::LoadLongI(_) => 0xF000,
+            Instruction::Plane(n) => xkk(0xF, n, 0x01),
+            Instruction::LoadAudio => 0xF002,
+            Instruction::LoadDelay(x) => xkk(0xF, x, 0x07),
+            Instruction::WaitKey(x) => xkk(0xF, x, 0x0A),
+            Instruction::SetDelay(x) => xkk(0xF, x, 0x15),
+            Instruction::SetSound(x) => xkk(0xF, x, 0x18),
+            Instruction::AddI(x) => xkk(0xF, x, 0x1E),
+            Instruction::LoadFont(x) => xkk(0xF, x, 0x29),
+            Instruction::LoadBigFont(x) => xkk(0xF, x, 0x30),
+            Instruction::SetPitch(x) => xkk(0xF, x, 0x3A),
+            Instruction::StoreBcd(x) => xkk(0xF, x, 0x33),
+            Instruction::StoreRegs(x) => xkk(0xF, x, 0x55),
+            Instruction::LoadRegs(x) => xkk(0xF, x, 0x65),
+            Instruction::StoreFlags(x) => xkk(0xF, x, 0x75),
+            Instruction::LoadFlags(x) => xkk(0xF, x, 0x85),
+        }
+    }
+
+    /// The first machine variant that has this instruction.
+    pub fn variant(&self) -> Variant {
+        match *self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+            | Instruction::LoadBigFont(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_) => Variant::SuperChip,
+            Instruction::ScrollUp(_)
+            | Instruction::SaveRange(..)
+            | Instruction::LoadRange(..)
+            | Instruction::LoadLongI(_)
+            | Instruction::Plane(_)
+            | Instruction::LoadAudio
+            | Instruction::SetPitch(_) => Variant::XoChip,
+            _ => Variant::Chip8,
+        }
+    }
+}
+
+/// Mnemonics used by the disassembler.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD #${:x}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU #${:x}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump(nnn) => write!(f, "JUMP ${:03x}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL ${:03x}", nnn),
+            Instruction::SkipEqByte(x, kk) => write!(f, "SE V{:X}, #${:02x}", x, kk),
+            Instruction::SkipNeqByte(x, kk) => write!(f, "SNE V{:X}, #${:02x}", x, kk),
+            Instruction::SkipEqReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::LoadByte(x, kk) => write!(f, "LD V{:X}, #${:02x}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, #${:02x}", x, kk),
+            Instruction::LoadReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeqReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, ${:03x}", nnn),
+            Instruction::JumpV0(nnn) => write!(f, "JUMP V0, ${:03x}", nnn),
+            Instruction::Random(x, kk) => write!(f, "RND V{:X}, #${:02x}", x, kk),
+            Instruction::Draw(x, y, n) => write!(f, "DRAW V{:X}, V{:X}, #${:x}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNoKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadLongI(nnnn) => write!(f, "LD I, LONG ${:04x}", nnnn),
+            Instruction::Plane(n) => write!(f, "PLANE #${:x}", n),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LoadBigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::SetPitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::StoreBcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegs(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegs(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_is_the_inverse_of_decode() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_the_fields() {
+        assert_eq!(Instruction::decode(0x8AB5), Ok(Instruction::Sub(0xA, 0xB)));
+        assert_eq!(
+            Instruction::decode(0xD12F),
+            Ok(Instruction::Draw(1, 2, 0xF))
+        );
+        assert_eq!(Instruction::decode(0x2ABC), Ok(Instruction::Call(0xABC)));
+        assert_eq!(Instruction::decode(0x00C4), Ok(Instruction::ScrollDown(4)));
+        assert_eq!(
+            Instruction::decode_long(0xF000, 0x1234),
+            Ok(Instruction::LoadLongI(0x1234))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_opcodes() {
+        for opcode in [0x0000, 0x5121, 0x8008, 0x9001, 0xE000, 0xF0FF, 0xF000] {
+            assert_eq!(Instruction::decode(opcode), Err(DecodeError { opcode }));
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::error::Error;
+use std::fmt;
+
+/// The hex keypad of the COSMAC VIP, row by row. Keymaps bind a 4x4 block of the host
+/// keyboard to it.
+pub const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// The host keys besides the letters and digits, named like the window library names
+/// them.
+const NAMED_KEYS: [&str; 70] = [
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+    "F11",
+    "F12",
+    "F13",
+    "F14",
+    "F15",
+    "Down",
+    "Left",
+    "Right",
+    "Up",
+    "Apostrophe",
+    "Backquote",
+    "Backslash",
+    "Comma",
+    "Equal",
+    "LeftBracket",
+    "Minus",
+    "Period",
+    "RightBracket",
+    "Semicolon",
+    "Slash",
+    "Backspace",
+    "Delete",
+    "End",
+    "Enter",
+    "Escape",
+    "Home",
+    "Insert",
+    "Menu",
+    "PageDown",
+    "PageUp",
+    "Pause",
+    "Space",
+    "Tab",
+    "NumLock",
+    "CapsLock",
+    "ScrollLock",
+    "LeftShift",
+    "RightShift",
+    "LeftCtrl",
+    "RightCtrl",
+    "NumPad0",
+    "NumPad1",
+    "NumPad2",
+    "NumPad3",
+    "NumPad4",
+    "NumPad5",
+    "NumPad6",
+    "NumPad7",
+    "NumPad8",
+    "NumPad9",
+    "NumPadDot",
+    "NumPadSlash",
+    "NumPadAsterisk",
+    "NumPadMinus",
+    "NumPadPlus",
+    "NumPadEnter",
+    "LeftAlt",
+    "RightAlt",
+    "LeftSuper",
+    "RightSuper",
+];
+
+/// Whether `name` is the name of a host key, ignoring the case.
+fn is_host_key(name: &str) -> bool {
+    let single = name.len() == 1 && name.bytes().all(|b| b.is_ascii_alphanumeric());
+
+    single || NAMED_KEYS.iter().any(|key| key.eq_ignore_ascii_case(name))
+}
+
+/// Binds host keys to the 16 keys of the keypad.
+///
+/// Host keys are named like the keys of the keyboard: `1`, `Q`, `Semicolon`, `Up`,
+/// `NumPad0`... Names are case insensitive. Several host keys can be bound to the same
+/// keypad key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    /// Host key names in uppercase, and the keypad key they are bound to.
+    bindings: Vec<(String, u8)>,
+}
+
+impl Keymap {
+    /// A keymap without any binding.
+    pub fn empty() -> Keymap {
+        Keymap {
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Binds the 4x4 block of host keys `rows` to [`KEYPAD_LAYOUT`].
+    pub fn from_rows(rows: [[&str; 4]; 4]) -> Keymap {
+        let mut keymap = Keymap::empty();
+
+        for (names, keys) in rows.iter().zip(KEYPAD_LAYOUT.iter()) {
+            for (name, key) in names.iter().zip(keys.iter()) {
+                keymap.bind(name, *key);
+            }
+        }
+
+        keymap
+    }
+
+    /// The conventional layout, `1234`, `QWER`, `ASDF` and `ZXCV` on a QWERTY keyboard.
+    pub fn qwerty() -> Keymap {
+        Keymap::from_rows([
+            ["1", "2", "3", "4"],
+            ["Q", "W", "E", "R"],
+            ["A", "S", "D", "F"],
+            ["Z", "X", "C", "V"],
+        ])
+    }
+
+    /// The same keys as [`Keymap::qwerty`] on a French AZERTY keyboard.
+    pub fn azerty() -> Keymap {
+        Keymap::from_rows([
+            ["1", "2", "3", "4"],
+            ["A", "Z", "E", "R"],
+            ["Q", "S", "D", "F"],
+            ["W", "X", "C", "V"],
+        ])
+    }
+
+    /// The same keys as [`Keymap::qwerty`] on a German QWERTZ keyboard.
+    pub fn qwertz() -> Keymap {
+        Keymap::from_rows([
+            ["1", "2", "3", "4"],
+            ["Q", "W", "E", "R"],
+            ["A", "S", "D", "F"],
+            ["Y", "X", "C", "V"],
+        ])
+    }
+
+    /// The same keys as [`Keymap::qwerty`] on a Dvorak keyboard.
+    pub fn dvorak() -> Keymap {
+        Keymap::from_rows([
+            ["1", "2", "3", "4"],
+            ["Apostrophe", "Comma", "Period", "P"],
+            ["A", "O", "E", "U"],
+            ["Semicolon", "Q", "J", "K"],
+        ])
+    }
+
+    /// Looks up a preset by name: `qwerty`, `azerty`, `qwertz` or `dvorak`.
+    pub fn preset(name: &str) -> Option<Keymap> {
+        match name.to_lowercase().as_str() {
+            "qwerty" => Some(Keymap::qwerty()),
+            "azerty" => Some(Keymap::azerty()),
+            "qwertz" => Some(Keymap::qwertz()),
+            "dvorak" => Some(Keymap::dvorak()),
+            _ => None,
+        }
+    }
+
+    /// Binds `host_key` to the keypad key `key`, replacing its previous binding.
+    pub fn bind(&mut self, host_key: &str, key: u8) {
+        self.unbind(host_key);
+        self.bindings.push((host_key.to_uppercase(), key & 0xf));
+    }
+
+    /// Removes the binding of `host_key`, if any.
+    pub fn unbind(&mut self, host_key: &str) {
+        let host_key = host_key.to_uppercase();
+        self.bindings.retain(|(name, _)| *name != host_key);
+    }
+
+    /// The keypad key bound to `host_key`.
+    pub fn get(&self, host_key: &str) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host_key))
+            .map(|(_, key)| *key)
+    }
+
+    /// The host keys bound to the keypad key `key`.
+    pub fn host_keys(&self, key: u8) -> impl Iterator<Item = &str> {
+        self.bindings
+            .iter()
+            .filter(move |(_, k)| *k == key)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::qwerty()
+    }
+}
+
+/// A syntax error in a keymap file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeymapError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "keymap line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for KeymapError {}
+
+/// The keymaps of a keymap file: one for every ROM and overrides for some of them.
+///
+/// ```text
+/// # The default keymap, it starts from the qwerty preset.
+/// preset = azerty
+/// Space = 5
+///
+/// # Only for this ROM, on top of the default keymap.
+/// [game_sub.ch8]
+/// Left = 4
+/// Right = 6
+/// P = none
+/// ```
+///
+/// `preset` starts over from a preset, `<host key> = <hex digit>` binds a key and
+/// `<host key> = none` removes its binding. A section applies to the ROM with that file
+/// name, with or without its extension.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeymapFile {
+    default: Vec<Line>,
+    roms: Vec<(String, Vec<Line>)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Line {
+    Preset(String),
+    Bind(String, u8),
+    Unbind(String),
+}
+
+impl KeymapFile {
+    pub fn parse(text: &str) -> Result<KeymapFile, KeymapError> {
+        let mut file = KeymapFile::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| KeymapError {
+                line: i + 1,
+                message,
+            };
+
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                let rom = line
+                    .strip_prefix('[')
+                    .and_then(|l| l.strip_suffix(']'))
+                    .map(str::trim)
+                    .filter(|rom| !rom.is_empty())
+                    .ok_or_else(|| error(format!("bad section header `{}`", line)))?;
+
+                file.roms.push((rom.to_string(), Vec::new()));
+                continue;
+            }
+
+            let (name, value) = match line.find('=') {
+                Some(pos) => (line[..pos].trim(), line[pos + 1..].trim()),
+                None => return Err(error(format!("expected `key = value`, got `{}`", line))),
+            };
+
+            let parsed = if name.eq_ignore_ascii_case("preset") {
+                if Keymap::preset(value).is_none() {
+                    return Err(error(format!("unknown preset `{}`", value)));
+                }
+                Line::Preset(value.to_string())
+            } else if !is_host_key(name) {
+                return Err(error(format!("unknown key `{}`", name)));
+            } else if value.eq_ignore_ascii_case("none") {
+                Line::Unbind(name.to_string())
+            } else {
+                match u8::from_str_radix(value, 16) {
+                    Ok(key) if key <= 0xF => Line::Bind(name.to_string(), key),
+                    _ => return Err(error(format!("`{}` is not a keypad key", value))),
+                }
+            };
+
+            match file.roms.last_mut() {
+                Some((_, lines)) => lines.push(parsed),
+                None => file.default.push(parsed),
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// The keymap for every ROM without an override.
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = Keymap::default();
+        apply(&mut keymap, &self.default);
+
+        keymap
+    }
+
+    /// The keymap for the ROM with the file name `rom`, like `game_sub.ch8`.
+    pub fn keymap_for(&self, rom: &str) -> Keymap {
+        let mut keymap = self.keymap();
+        let stem = rom.rsplit_once('.').map_or(rom, |(stem, _)| stem);
+
+        for (section, lines) in &self.roms {
+            if section.eq_ignore_ascii_case(rom) || section.eq_ignore_ascii_case(stem) {
+                apply(&mut keymap, lines);
+            }
+        }
+
+        keymap
+    }
+}
+
+fn apply(keymap: &mut Keymap, lines: &[Line]) {
+    for line in lines {
+        match line {
+            Line::Preset(name) => *keymap = Keymap::preset(name).unwrap_or_default(),
+            Line::Bind(host_key, key) => keymap.bind(host_key, *key),
+            Line::Unbind(host_key) => keymap.unbind(host_key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> (usize, String) {
+        let e = KeymapFile::parse(text).unwrap_err();
+        (e.line, e.message)
+    }
+
+    #[test]
+    fn default_layout() {
+        let keymap = Keymap::default();
+        let layout = ["1234", "QWER", "ASDF", "ZXCV"];
+
+        for (names, keys) in layout.iter().zip(KEYPAD_LAYOUT.iter()) {
+            for (name, key) in names.chars().zip(keys.iter()) {
+                assert_eq!(keymap.get(&name.to_string()), Some(*key));
+            }
+        }
+        assert_eq!(keymap.get("q"), Some(0x4));
+        assert_eq!(keymap.host_keys(0x0).collect::<Vec<_>>(), ["X"]);
+        assert_eq!(KeymapFile::parse("").unwrap().keymap(), keymap);
+    }
+
+    #[test]
+    fn rom_sections_override_the_default() {
+        let file = KeymapFile::parse(
+            "preset = azerty\n\
+             Space = 5\n\
+             [game_sub.ch8]\n\
+             Left = 4\n\
+             Space = 6\n\
+             Z = none\n\
+             [pong]\n\
+             1 = none\n",
+        )
+        .unwrap();
+
+        let default = file.keymap();
+        assert_eq!(default.get("A"), Some(0x4));
+        assert_eq!(default.get("Space"), Some(0x5));
+        assert_eq!(default.get("Left"), None);
+        assert_eq!(file.keymap_for("other.ch8"), default);
+
+        for rom in ["game_sub.ch8", "GAME_SUB.CH8"] {
+            let keymap = file.keymap_for(rom);
+            assert_eq!(keymap.get("Left"), Some(0x4));
+            assert_eq!(keymap.get("Space"), Some(0x6));
+            assert_eq!(keymap.get("Z"), None);
+            assert_eq!(keymap.get("A"), Some(0x4));
+            assert_eq!(keymap.get("1"), Some(0x1));
+        }
+
+        // Sections without an extension match any.
+        assert_eq!(file.keymap_for("pong.ch8").get("1"), None);
+        assert_eq!(file.keymap_for("pong.ch8").get("Space"), Some(0x5));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(
+            parse_error("Q = 1\n\nFoo = 2"),
+            (3, "unknown key `Foo`".to_string())
+        );
+        assert_eq!(
+            parse_error("Q = 10"),
+            (1, "`10` is not a keypad key".to_string())
+        );
+        assert_eq!(
+            parse_error("# keys\nQ = G"),
+            (2, "`G` is not a keypad key".to_string())
+        );
+        assert_eq!(
+            parse_error("preset = colemak"),
+            (1, "unknown preset `colemak`".to_string())
+        );
+        assert_eq!(parse_error("[game.ch8\nQ = 1").0, 1);
+        assert_eq!(parse_error("Q 1").0, 1);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let file = KeymapFile::parse("# comment\n\n   \n  Q = a # trailing\n\t\n").unwrap();
+
+        assert_eq!(file.keymap().get("Q"), Some(0xA));
+        assert_eq!(file.keymap().host_keys(0x4).count(), 0);
+    }
+}
@@ -0,0 +1,133 @@
+/// The 16 keys of the hex keypad, `0` to `F`.
+///
+/// Frontends report every key going down and up. Besides the current state the keypad
+/// remembers the presses and releases of the current frame, so a key tapped between
+/// two frames is still seen by `Fx0A`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keypad {
+    /// One bit per key that is held down.
+    down: u16,
+    /// Keys that went down during the current frame.
+    pressed: u16,
+    /// Keys that went up during the current frame.
+    released: u16,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad::default()
+    }
+
+    /// A key went down, keys past `F` are ignored.
+    pub fn press(&mut self, key: u8) {
+        if key > 0xF {
+            return;
+        }
+
+        self.down |= 1 << key;
+        self.pressed |= 1 << key;
+    }
+
+    /// A key went up, keys past `F` are ignored.
+    pub fn release(&mut self, key: u8) {
+        if key > 0xF {
+            return;
+        }
+
+        if self.down & (1 << key) != 0 {
+            self.down &= !(1 << key);
+            self.released |= 1 << key;
+        }
+    }
+
+    /// Releases every key, for example when the window loses focus.
+    pub fn release_all(&mut self) {
+        for key in 0..16 {
+            self.release(key);
+        }
+    }
+
+    pub fn is_down(&self, key: u8) -> bool {
+        key <= 0xF && self.down & (1 << key) != 0
+    }
+
+    /// One bit per key that is held down, for save states.
+    pub(crate) fn down_mask(&self) -> u16 {
+        self.down
+    }
+
+    /// A keypad with the keys of `down_mask` held.
+    pub(crate) fn from_down_mask(down: u16) -> Keypad {
+        Keypad {
+            down,
+            ..Keypad::default()
+        }
+    }
+
+    /// The first key that is down or went down during this frame.
+    pub(crate) fn first_pressed(&self) -> Option<u8> {
+        let keys = self.down | self.pressed;
+
+        (0..16).find(|key| keys & (1 << key) != 0)
+    }
+
+    /// Whether `key` is up, or was released during this frame.
+    pub(crate) fn was_released(&self, key: u8) -> bool {
+        !self.is_down(key) || self.released & (1 << key) != 0
+    }
+
+    /// Forgets the presses and releases of `key` in this frame, once `Fx0A` used them.
+    pub(crate) fn consume(&mut self, key: u8) {
+        self.pressed &= !(1 << key);
+        self.released &= !(1 << key);
+    }
+
+    /// Starts a new frame, forgetting the presses and releases of the previous one.
+    pub(crate) fn end_frame(&mut self) {
+        self.pressed = 0;
+        self.released = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taps_last_until_the_end_of_the_frame() {
+        let mut keypad = Keypad::new();
+        keypad.press(3);
+        keypad.release(3);
+
+        assert!(!keypad.is_down(3));
+        assert_eq!(keypad.first_pressed(), Some(3));
+        assert!(keypad.was_released(3));
+
+        keypad.end_frame();
+        assert_eq!(keypad.first_pressed(), None);
+    }
+
+    #[test]
+    fn held_keys_survive_the_end_of_the_frame() {
+        let mut keypad = Keypad::new();
+        keypad.press(0xA);
+        keypad.end_frame();
+
+        assert_eq!(keypad.first_pressed(), Some(0xA));
+        assert!(!keypad.was_released(0xA));
+
+        keypad.release(0xA);
+        assert!(keypad.was_released(0xA));
+        keypad.consume(0xA);
+        assert_eq!(keypad.first_pressed(), None);
+    }
+
+    #[test]
+    fn ignores_keys_past_f() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x10);
+
+        assert_eq!(keypad, Keypad::new());
+        assert!(!keypad.is_down(0x10));
+    }
+}
@@ -0,0 +1,96 @@
+//! CHIP-8 Emulator/interpreter documentation
+//!
+//! # Reading instructions
+//! Each instrution is 2 bytes wide. They are stored in `mem[0x200..0x1000]`.
+//! We read the instructions at the PC (_program counter_), each time one instruction is read,
+//! the program counter *have to be* increased by one, unless the instruction states otherwise.
+//! To read the instructions you do `CpuState.mem[CpuState.pc]` and `CpuState.mem[CpuState.pc + 1]`.
+//!
+//! # Using the library
+//! The [`Chip8`] type is the whole machine, frontends only have to feed it keys,
+//! call [`Chip8::run_frame`] once per frame and draw [`Chip8::framebuffer`].
+//!
+//! ```no_run
+//! let rom = std::fs::read("./roms/game_sub.ch8").unwrap();
+//!
+//! let mut chip8 = chip8::Chip8::new();
+//! chip8.load_rom(&rom).unwrap();
+//!
+//! chip8.key_down(0x5);
+//! chip8.run_frame().unwrap();
+//!
+//! let _pixels: &[u32] = chip8.framebuffer();
+//! ```
+//!
+//! # Sound
+//! The sound goes to an [`AudioSink`]: the speakers with [`Speaker`] (behind the `audio`
+//! feature), or a `.wav` file with [`WavSink`].
+//!
+//! ```no_run
+//! let mut chip8 = chip8::Chip8::new();
+//! chip8.set_audio_sink(Box::new(chip8::WavSink::create("beep.wav").unwrap()));
+//! ```
+//!
+//! # Screenshots and recordings
+//! [`Chip8::write_screenshot`] saves the screen as a PNG. A [`VideoSink`] receives every
+//! frame: [`GifSink`] records an animated GIF, [`Y4mSink`] and [`RawVideoSink`] write
+//! uncompressed video for other tools.
+//!
+//! ```no_run
+//! let mut chip8 = chip8::Chip8::new();
+//! chip8.set_video_sink(Box::new(chip8::GifSink::create("game.gif", 2).unwrap()));
+//! ```
+
+mod audio;
+mod config;
+mod cpu;
+mod debugger;
+mod disasm;
+mod display;
+mod error;
+mod expr;
+mod font;
+mod image;
+mod instruction;
+mod keymap;
+mod keypad;
+mod machine;
+mod memory;
+mod quirks;
+mod rewind;
+mod rng;
+mod savestate;
+mod scheduler;
+mod script;
+#[cfg(feature = "audio")]
+mod speaker;
+mod stack;
+mod video;
+mod wav;
+
+pub use audio::{AudioSink, Sound, Synth, Tone, Waveform};
+pub use config::{Config, Variant, INSTRUCTIONS_PER_FRAME, XO_CHIP_MEMORY_SIZE};
+pub use cpu::{AccessKind, CpuState, MemoryAccess};
+pub use debugger::{
+    Breakpoint, Debugger, LogEntry, StopReason, WatchAction, WatchKind, Watchpoint,
+};
+pub use display::{Display, Palette, DEFAULT_PALETTE, H, HIRES_H, HIRES_W, W};
+pub use error::Chip8Error;
+pub use expr::{Expr, ExprError, Template};
+pub use font::{FontSet, BIG_FONT, FONT_BASE};
+pub use instruction::{DecodeError, Instruction};
+pub use keymap::{Keymap, KeymapError, KeymapFile, KEYPAD_LAYOUT};
+pub use keypad::Keypad;
+pub use machine::{Chip8, ErrorPolicy, StepOutcome, TrapAction};
+pub use memory::{OutOfBounds, MEMORY_SIZE, PROGRAM_START};
+pub use quirks::Quirks;
+pub use rewind::Rewind;
+pub use rng::{Rng, RngKind};
+pub use savestate::{SaveSlots, SaveStateError, SAVE_STATE_VERSION};
+pub use scheduler::{FrameLimiter, FRAME_RATE};
+pub use script::{InputScript, ScriptError};
+#[cfg(feature = "audio")]
+pub use speaker::Speaker;
+pub use stack::{CallStack, StackConfig};
+pub use video::{GifSink, RawVideoSink, VideoSink, Y4mSink};
+pub use wav::{WavSink, WAV_SAMPLE_RATE};
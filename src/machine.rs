@@ -0,0 +1,736 @@
+use crate::audio::{AudioSink, Sound, Synth, Tone};
+use crate::config::Config;
+use crate::cpu::{CpuState, MemoryAccess};
+use crate::display::Palette;
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::keypad::Keypad;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+use crate::savestate::{self, SaveStateError};
+use crate::scheduler::FRAME_RATE;
+use crate::stack::CallStack;
+use crate::video::VideoSink;
+use std::io::{self, Write};
+
+/// What the machine does after an instruction failed.
+pub enum ErrorPolicy {
+    /// Stop executing, [`Chip8::step`] returns the error.
+    Halt,
+    /// Ignore the failing instruction and continue with the next one.
+    Skip,
+    /// Let a callback decide, for example to log the error before skipping.
+    Trap(Box<dyn FnMut(&Chip8Error) -> TrapAction>),
+}
+
+/// The decision of an [`ErrorPolicy::Trap`] callback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapAction {
+    Halt,
+    Skip,
+}
+
+/// The result of a successful call to [`Chip8::step`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction was executed.
+    Executed(Instruction),
+    /// The instruction failed and was skipped because of the [`ErrorPolicy`].
+    Skipped(Chip8Error),
+    /// The machine halted on an earlier error or exited, nothing was executed.
+    Halted,
+}
+
+/// A complete CHIP-8 machine, this is what frontends are supposed to drive.
+pub struct Chip8 {
+    cpu: CpuState,
+    config: Config,
+    error_policy: ErrorPolicy,
+    halted: Option<Chip8Error>,
+    /// Set by the SUPER-CHIP `EXIT` instruction.
+    exited: bool,
+    /// Instructions executed since the timers last ticked, the timers tick every
+    /// `instructions_per_frame` instructions, 60 times per emulated second.
+    cycles: usize,
+    /// Whether the memory accesses of the instructions are recorded.
+    trace_memory: bool,
+    audio: Option<Audio>,
+    video: Option<Box<dyn VideoSink>>,
+}
+
+/// Where the sound goes, and the state to generate it.
+struct Audio {
+    sink: Box<dyn AudioSink>,
+    synth: Synth,
+    /// Reused for every frame.
+    buffer: Vec<f32>,
+}
+
+impl Chip8 {
+    /// Creates a machine with empty memory (only the font is loaded).
+    pub fn new() -> Chip8 {
+        Chip8::with_config(Config::default())
+    }
+
+    /// Creates a machine with custom options.
+    ///
+    /// Panics if the fonts don't fit below `0x200` or `config.memory_size` is too small
+    /// for that area, see [`Chip8::try_with_config`].
+    pub fn with_config(config: Config) -> Chip8 {
+        match Chip8::try_with_config(config) {
+            Ok(chip8) => chip8,
+            Err(e) => panic!("invalid config: {}", e),
+        }
+    }
+
+    /// Like [`Chip8::with_config`], but fails with [`Chip8Error::MemoryTooSmall`] or
+    /// [`Chip8Error::FontOverlapsProgram`] instead of panicking.
+    pub fn try_with_config(mut config: Config) -> Result<Chip8, Chip8Error> {
+        let cpu = CpuState::new(&[], &config)?;
+        config.instructions_per_frame = config.instructions_per_frame.max(1);
+
+        Ok(Chip8 {
+            cpu,
+            config,
+            error_policy: ErrorPolicy::Halt,
+            halted: None,
+            exited: false,
+            cycles: 0,
+            trace_memory: false,
+            audio: None,
+            video: None,
+        })
+    }
+
+    /// Resets the machine and loads `rom` at `0x200`.
+    ///
+    /// Fails with [`Chip8Error::RomTooLarge`] if the ROM doesn't fit in memory, the
+    /// machine is left untouched in that case.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        // The keys that are held stay held.
+        let keypad = self.cpu.keypad;
+        self.cpu = CpuState::new(rom, &self.config)?;
+        self.cpu.keypad = keypad;
+        self.halted = None;
+        self.exited = false;
+        self.cycles = 0;
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Selects what happens when an instruction fails, the default is [`ErrorPolicy::Halt`].
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    /// The error that halted the machine, if any.
+    pub fn halted(&self) -> Option<Chip8Error> {
+        self.halted
+    }
+
+    /// Whether the ROM ended with the SUPER-CHIP `EXIT` instruction.
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Executes a single instruction.
+    ///
+    /// An error is returned only once, when the machine halts. After that every step
+    /// returns [`StepOutcome::Halted`] until a new ROM is loaded. The same happens after
+    /// the SUPER-CHIP `EXIT` instruction, without the error.
+    pub fn step(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.halted.is_some() || self.exited {
+            return Ok(StepOutcome::Halted);
+        }
+
+        if self.trace_memory {
+            self.cpu.accesses.get_or_insert_with(Vec::new).clear();
+        }
+
+        let outcome = self.execute_one()?;
+
+        if let StepOutcome::Executed(Instruction::Exit) = outcome {
+            self.exited = true;
+        }
+
+        match outcome {
+            // Waiting for the vertical blank, the rest of the frame is spent idle.
+            StepOutcome::Executed(Instruction::Draw(..)) if self.config.quirks.display_wait => {
+                self.end_frame()
+            }
+            _ => self.advance_clock(),
+        }
+
+        Ok(outcome)
+    }
+
+    /// Runs the instruction at `pc` and applies the error policy.
+    fn execute_one(&mut self) -> Result<StepOutcome, Chip8Error> {
+        let e = match self.cpu.emulate_chip8(&self.config) {
+            Ok(instruction) => return Ok(StepOutcome::Executed(instruction)),
+            Err(e) => e,
+        };
+
+        let action = match self.error_policy {
+            ErrorPolicy::Halt => TrapAction::Halt,
+            ErrorPolicy::Skip => TrapAction::Skip,
+            ErrorPolicy::Trap(ref mut callback) => callback(&e),
+        };
+
+        match (action, e) {
+            // There is no next instruction to skip to.
+            (_, Chip8Error::PcOutOfBounds { .. }) | (TrapAction::Halt, _) => {
+                self.halted = Some(e);
+                Err(e)
+            }
+            (TrapAction::Skip, _) => {
+                self.cpu.pc += 2;
+                Ok(StepOutcome::Skipped(e))
+            }
+        }
+    }
+
+    /// Counts one instruction of emulated time, ticking the timers when a frame has passed.
+    fn advance_clock(&mut self) {
+        self.cycles += 1;
+
+        if self.cycles >= self.config.instructions_per_frame {
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.cycles = 0;
+
+        let sound = self.sound();
+        if let Some(audio) = self.audio.as_mut() {
+            audio.buffer.clear();
+            audio
+                .synth
+                .render_frame(sound, FRAME_RATE, &mut audio.buffer);
+            audio.sink.write(&audio.buffer);
+        }
+
+        if let Some(video) = self.video.as_mut() {
+            video.write(&self.cpu.display);
+        }
+
+        self.cpu.tick_timers();
+        self.cpu.keypad.end_frame();
+    }
+
+    /// What the machine is playing, decided by the sound timer.
+    pub fn sound(&self) -> Sound {
+        if self.cpu.sound == 0 {
+            return Sound::Silent;
+        }
+
+        match self.cpu.audio_pattern {
+            Some(pattern) => Sound::Pattern {
+                pattern,
+                pitch: self.cpu.pitch,
+            },
+            None => Sound::Buzzer,
+        }
+    }
+
+    /// Sends the sound to `sink`, one frame at a time, replacing the previous sink.
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        let synth = Synth::new(self.config.tone, sink.sample_rate());
+
+        self.audio = Some(Audio {
+            sink,
+            synth,
+            buffer: Vec::new(),
+        });
+    }
+
+    /// Detaches the audio sink, [`AudioSink::flush`] reports if anything went wrong with it.
+    pub fn remove_audio_sink(&mut self) -> Option<Box<dyn AudioSink>> {
+        self.audio.take().map(|audio| audio.sink)
+    }
+
+    /// Sends the screen to `sink` at the end of every frame, replacing the previous sink.
+    pub fn set_video_sink(&mut self, sink: Box<dyn VideoSink>) {
+        self.video = Some(sink);
+    }
+
+    /// Detaches the video sink, [`VideoSink::finish`] ends the recording.
+    pub fn remove_video_sink(&mut self) -> Option<Box<dyn VideoSink>> {
+        self.video.take()
+    }
+
+    /// Snapshots the whole machine: registers, stack, timers, memory, screen, keys, RNG
+    /// and quirks. See [`SaveSlots`] to keep states on disk.
+    ///
+    /// [`SaveSlots`]: crate::SaveSlots
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::encode(&self.cpu, &self.config, self.exited, self.cycles)
+    }
+
+    /// Restores a state made by [`Chip8::save_state`], the machine is left untouched if
+    /// it can't be read.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let snapshot = savestate::decode(data, &self.config)?;
+
+        self.config.variant = snapshot.variant;
+        self.config.quirks = snapshot.quirks;
+        self.config.memory_size = snapshot.cpu.mem.len();
+        self.config.stack = snapshot.cpu.stack.config();
+        self.cpu = snapshot.cpu;
+        self.halted = None;
+        self.exited = snapshot.exited;
+        self.cycles = snapshot
+            .cycles
+            .min(self.config.instructions_per_frame.saturating_sub(1));
+
+        Ok(())
+    }
+
+    /// The seed of the random number generator, to reproduce this run later with
+    /// [`Config::seed`].
+    pub fn seed(&self) -> u64 {
+        self.cpu.rng.seed()
+    }
+
+    /// Restarts the random number generator from `seed`, also for the next ROMs.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.config.seed = Some(seed);
+        self.cpu.rng = Rng::new(self.config.rng, seed);
+    }
+
+    /// Changes the sound of the buzzer, it can be called at any time.
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.config.tone = tone;
+
+        if let Some(audio) = self.audio.as_mut() {
+            audio.synth.set_tone(tone);
+        }
+    }
+
+    /// Executes all the instructions of one 60 Hz frame, stopping early if the machine
+    /// halts. The timers tick once per frame.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        // Also finishes a frame that a few calls to `step` may have started.
+        loop {
+            if let StepOutcome::Halted = self.step()? {
+                break;
+            }
+
+            if self.cycles == 0 {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records the memory every instruction reads and writes, for watchpoints. Off by
+    /// default, it slows the machine down a bit.
+    pub fn set_memory_tracing(&mut self, enabled: bool) {
+        self.trace_memory = enabled;
+        if !enabled {
+            self.cpu.accesses = None;
+        }
+    }
+
+    /// The memory the last instruction read and wrote, in order. Always empty without
+    /// [`Chip8::set_memory_tracing`].
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        self.cpu.accesses.as_deref().unwrap_or(&[])
+    }
+
+    /// How many instructions of the current frame were executed, 0 right after a frame
+    /// ended.
+    pub fn frame_cycles(&self) -> usize {
+        self.cycles
+    }
+
+    /// How many instructions are executed per frame.
+    pub fn speed(&self) -> usize {
+        self.config.instructions_per_frame
+    }
+
+    /// The quirks in use, they can be changed at any time.
+    pub fn quirks(&self) -> Quirks {
+        self.config.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.config.quirks = quirks;
+    }
+
+    /// Changes how many instructions are executed per frame, it can be called at any time.
+    pub fn set_speed(&mut self, instructions_per_frame: usize) {
+        self.config.instructions_per_frame = instructions_per_frame.max(1);
+        self.cycles = self
+            .cycles
+            .min(self.config.instructions_per_frame.saturating_sub(1));
+    }
+
+    /// The screen, `width * height` pixels in `0RGB` format, row by row.
+    /// See [`Chip8::screen_size`] for its current size.
+    pub fn framebuffer(&self) -> &[u32] {
+        self.cpu.display.pixels()
+    }
+
+    /// Writes the screen as a PNG in the current colors, every pixel of the screen is a
+    /// `scale * scale` square of the image.
+    pub fn write_screenshot<W: Write>(&self, out: W, scale: usize) -> io::Result<()> {
+        self.cpu.display.write_png_scaled(out, scale)
+    }
+
+    /// Changes the colors of the screen, it can be called at any time.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.config.palette = palette;
+        self.cpu.display.set_palette(palette);
+    }
+
+    /// The size of the screen, (64, 32), or (128, 64) in SUPER-CHIP high resolution.
+    pub fn screen_size(&self) -> (usize, usize) {
+        (self.cpu.display.width(), self.cpu.display.height())
+    }
+
+    /// One of the 16 keys of the keypad went down.
+    pub fn key_down(&mut self, key: u8) {
+        self.cpu.keypad.press(key);
+    }
+
+    /// One of the 16 keys of the keypad went up.
+    pub fn key_up(&mut self, key: u8) {
+        self.cpu.keypad.release(key);
+    }
+
+    /// Calls [`Chip8::key_down`] or [`Chip8::key_up`], the state only changes if the key
+    /// wasn't already in it.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if pressed == self.cpu.keypad.is_down(key) {
+            return;
+        }
+
+        if pressed {
+            self.key_down(key);
+        } else {
+            self.key_up(key);
+        }
+    }
+
+    /// Releases every key, for example when the window loses focus.
+    pub fn release_all_keys(&mut self) {
+        self.cpu.keypad.release_all();
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.cpu.keypad
+    }
+
+    /// Whether the machine is blocked on `Fx0A`, waiting for a key.
+    pub fn waiting_for_key(&self) -> bool {
+        matches!(
+            self.cpu.instruction_at(self.cpu.pc),
+            Ok(Instruction::WaitKey(_))
+        ) && self.halted.is_none()
+    }
+
+    /// The current value of the delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.cpu.delay
+    }
+
+    /// The current value of the sound timer.
+    pub fn sound_timer(&self) -> u8 {
+        self.cpu.sound
+    }
+
+    /// Whether the buzzer should be sounding right now.
+    pub fn is_beeping(&self) -> bool {
+        self.cpu.sound > 0
+    }
+
+    /// The return addresses of the subroutines being executed.
+    pub fn stack(&self) -> &CallStack {
+        &self.cpu.stack
+    }
+
+    /// Disassembles the instruction the program counter points to.
+    pub fn disassemble_current(&self) -> String {
+        self.cpu.disassemble_at(self.cpu.pc)
+    }
+
+    /// Read only access to the registers and memory.
+    pub fn cpu(&self) -> &CpuState {
+        &self.cpu
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::OutOfBounds;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn zero_instructions_per_frame_is_clamped() {
+        let mut chip8 = Chip8::with_config(Config {
+            instructions_per_frame: 0,
+            ..Config::default()
+        });
+        assert_eq!(chip8.speed(), 1);
+
+        // JP $200
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        chip8.run_frame().unwrap();
+        let state = chip8.save_state();
+        chip8.load_state(&state).unwrap();
+
+        chip8.set_speed(0);
+        assert_eq!(chip8.speed(), 1);
+    }
+
+    /// A machine running `LD V3, K` at `0x200`, then looping at `0x202`.
+    fn waiting() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0xF3, 0x0A, 0x12, 0x02]).unwrap();
+        chip8.run_frame().unwrap();
+
+        chip8
+    }
+
+    #[test]
+    fn wait_key_takes_the_key_once_released() {
+        let mut chip8 = waiting();
+        assert!(chip8.waiting_for_key());
+
+        chip8.key_down(5);
+        chip8.run_frame().unwrap();
+        chip8.run_frame().unwrap();
+        assert!(chip8.waiting_for_key());
+        assert_eq!(chip8.cpu().pc, 0x200);
+
+        chip8.key_up(5);
+        chip8.run_frame().unwrap();
+        assert!(!chip8.waiting_for_key());
+        assert_eq!(chip8.cpu().V[3], 5);
+        assert_eq!(chip8.cpu().pc, 0x202);
+    }
+
+    #[test]
+    fn wait_key_sees_taps_between_frames() {
+        let mut chip8 = waiting();
+
+        chip8.key_down(7);
+        chip8.key_up(7);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu().V[3], 7);
+        assert_eq!(chip8.cpu().pc, 0x202);
+    }
+
+    #[test]
+    fn taps_are_forgotten_after_their_frame() {
+        let mut chip8 = Chip8::new();
+        chip8.set_speed(1);
+        // JP $204; DW 0; LD V3, K; JP $206
+        chip8
+            .load_rom(&[0x12, 0x04, 0x00, 0x00, 0xF3, 0x0A, 0x12, 0x06])
+            .unwrap();
+
+        // The tap happens in the frame of the jump, it's gone when LD V3, K runs.
+        chip8.key_down(7);
+        chip8.key_up(7);
+        chip8.run_frame().unwrap();
+        chip8.run_frame().unwrap();
+        chip8.run_frame().unwrap();
+        assert!(chip8.waiting_for_key());
+        assert_eq!(chip8.cpu().pc, 0x204);
+    }
+
+    /// `LD V0, 1`, an unknown opcode, `LD V1, 2` and a loop.
+    fn faulty(policy: ErrorPolicy) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_error_policy(policy);
+        chip8
+            .load_rom(&[0x60, 0x01, 0x80, 0x08, 0x61, 0x02, 0x12, 0x06])
+            .unwrap();
+
+        chip8
+    }
+
+    const FAULT: Chip8Error = Chip8Error::UnknownOpcode {
+        addr: 0x202,
+        opcode: 0x8008,
+    };
+
+    #[test]
+    fn halt_stops_on_the_failing_instruction() {
+        let mut chip8 = faulty(ErrorPolicy::Halt);
+
+        assert!(matches!(chip8.step(), Ok(StepOutcome::Executed(_))));
+        assert_eq!(chip8.step(), Err(FAULT));
+        assert_eq!(chip8.halted(), Some(FAULT));
+        assert_eq!(chip8.cpu().pc, 0x202);
+
+        assert_eq!(chip8.step(), Ok(StepOutcome::Halted));
+        assert_eq!(chip8.run_frame(), Ok(()));
+        assert_eq!(chip8.cpu().pc, 0x202);
+        assert_eq!(chip8.cpu().V[1], 0);
+
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        assert_eq!(chip8.halted(), None);
+    }
+
+    #[test]
+    fn skip_continues_with_the_next_instruction() {
+        let mut chip8 = faulty(ErrorPolicy::Skip);
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.step(), Ok(StepOutcome::Skipped(FAULT)));
+        assert_eq!(chip8.cpu().pc, 0x204);
+        assert_eq!(chip8.halted(), None);
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.cpu().V[0], 1);
+        assert_eq!(chip8.cpu().V[1], 2);
+        assert_eq!(chip8.cpu().pc, 0x206);
+    }
+
+    #[test]
+    fn trap_lets_a_callback_decide() {
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&errors);
+        let mut chip8 = faulty(ErrorPolicy::Trap(Box::new(move |e| {
+            seen.borrow_mut().push(*e);
+            TrapAction::Skip
+        })));
+
+        chip8.run_frame().unwrap();
+        assert_eq!(*errors.borrow(), [FAULT]);
+        assert_eq!(chip8.cpu().V[1], 2);
+
+        let mut chip8 = faulty(ErrorPolicy::Trap(Box::new(|_| TrapAction::Halt)));
+        assert_eq!(chip8.run_frame(), Err(FAULT));
+        assert_eq!(chip8.halted(), Some(FAULT));
+        assert_eq!(chip8.cpu().pc, 0x202);
+    }
+
+    #[test]
+    fn pc_out_of_bounds_always_halts() {
+        let mut chip8 = Chip8::with_config(Config {
+            out_of_bounds: OutOfBounds::Error,
+            ..Config::default()
+        });
+        chip8.set_error_policy(ErrorPolicy::Skip);
+        // JP $FFF
+        chip8.load_rom(&[0x1F, 0xFF]).unwrap();
+
+        let error = Chip8Error::PcOutOfBounds { pc: 0xFFF };
+        assert_eq!(chip8.run_frame(), Err(error));
+        assert_eq!(chip8.halted(), Some(error));
+    }
+
+    /// A machine that loaded `FF` in both timers and loops at `0x206`, at the start of
+    /// a frame.
+    fn timers(instructions_per_frame: usize) -> Chip8 {
+        let mut chip8 = Chip8::with_config(Config {
+            instructions_per_frame,
+            ..Config::default()
+        });
+        // LD V0, #$FF; LD DT, V0; LD ST, V0; JP $206
+        chip8
+            .load_rom(&[0x60, 0xFF, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06])
+            .unwrap();
+        while chip8.cpu().pc != 0x206 {
+            chip8.run_frame().unwrap();
+        }
+
+        chip8
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame_at_any_speed() {
+        for ipf in [1, 2, 7, 30, 1000] {
+            let mut chip8 = timers(ipf);
+            let (delay, sound) = (chip8.delay_timer(), chip8.sound_timer());
+            // At 1 or 2 instructions per frame, DT is loaded a frame before ST.
+            assert!(delay >= 0xFF - 3, "{} instructions per frame", ipf);
+            assert!(sound >= delay);
+
+            for _ in 0..10 {
+                chip8.run_frame().unwrap();
+            }
+            assert_eq!(chip8.delay_timer(), delay - 10, "{} per frame", ipf);
+            assert_eq!(chip8.sound_timer(), sound - 10, "{} per frame", ipf);
+
+            // Single steps add up to the same frames.
+            for _ in 0..3 * ipf {
+                chip8.step().unwrap();
+            }
+            assert_eq!(chip8.delay_timer(), delay - 13, "{} per frame", ipf);
+            assert_eq!(chip8.sound_timer(), sound - 13, "{} per frame", ipf);
+        }
+    }
+
+    #[test]
+    fn timers_stop_at_zero() {
+        let mut chip8 = timers(10);
+        for _ in 0..300 {
+            chip8.run_frame().unwrap();
+        }
+
+        assert_eq!(chip8.delay_timer(), 0);
+        assert_eq!(chip8.sound_timer(), 0);
+        assert!(!chip8.is_beeping());
+    }
+
+    #[test]
+    fn set_speed_clamps_the_current_frame() {
+        let mut chip8 = timers(10);
+        let delay = chip8.delay_timer();
+
+        for _ in 0..5 {
+            chip8.step().unwrap();
+        }
+        chip8.set_speed(3);
+        assert_eq!(chip8.speed(), 3);
+        assert_eq!(chip8.frame_cycles(), 2);
+
+        // The frame already ran more than 3 instructions, the next one ends it.
+        chip8.step().unwrap();
+        assert_eq!(chip8.delay_timer(), delay - 1);
+        assert_eq!(chip8.frame_cycles(), 0);
+
+        chip8.set_speed(0);
+        assert_eq!(chip8.speed(), 1);
+        chip8.set_speed(100_000);
+        assert_eq!(chip8.speed(), 100_000);
+        assert_eq!(chip8.frame_cycles(), 0);
+    }
+
+    #[test]
+    fn display_wait_draws_one_sprite_per_frame() {
+        // DRW V0, V0, 1 three times, then a loop.
+        let rom = [0xD0, 0x01, 0xD0, 0x01, 0xD0, 0x01, 0x12, 0x06];
+
+        for (display_wait, pcs) in [(true, [0x202, 0x204, 0x206]), (false, [0x206; 3])] {
+            let mut config = Config::default();
+            config.quirks.display_wait = display_wait;
+            let mut chip8 = Chip8::with_config(config);
+            chip8.load_rom(&rom).unwrap();
+
+            for pc in pcs {
+                chip8.run_frame().unwrap();
+                assert_eq!(chip8.cpu().pc, pc, "display wait {}", display_wait);
+            }
+        }
+    }
+}
@@ -0,0 +1,16 @@
+/// Size of the address space of the original CHIP-8.
+pub const MEMORY_SIZE: usize = 0x1000;
+
+/// Where ROMs are loaded and execution starts.
+pub const PROGRAM_START: usize = 0x200;
+
+/// What happens when `I` or `pc` point past the end of memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutOfBounds {
+    /// Addresses wrap around, `0x1000` is `0x000` on a 4 KiB machine.
+    #[default]
+    Wrap,
+    /// The access fails with [`Chip8Error::MemoryOutOfBounds`](crate::Chip8Error::MemoryOutOfBounds)
+    /// or [`Chip8Error::PcOutOfBounds`](crate::Chip8Error::PcOutOfBounds).
+    Error,
+}
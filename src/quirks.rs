@@ -0,0 +1,117 @@
+/// Behaviours that changed between the CHIP-8 interpreters, ROMs written for one of
+/// them may break on the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift `Vx` in place and ignore `Vy`. When off, `Vx = Vy >> 1`
+    /// (or `Vy << 1`) like on the COSMAC VIP.
+    pub shift: bool,
+    /// `Fx55`/`Fx65` leave `I` pointing after the last register, `I += x + 1`.
+    pub memory_increment: bool,
+    /// `Bxnn` jumps to `xnn + Vx` instead of `nnn + V0`.
+    pub jump: bool,
+    /// `8xy1`, `8xy2` and `8xy3` reset `VF` to 0.
+    pub vf_reset: bool,
+    /// Sprites are cut at the edges of the screen instead of wrapping around.
+    pub clipping: bool,
+    /// `Dxyn` waits for the next frame, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original interpreter of the COSMAC VIP.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift: false,
+        memory_increment: true,
+        jump: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+    };
+
+    /// CHIP-48, for the HP-48 calculators.
+    pub const CHIP48: Quirks = Quirks {
+        shift: true,
+        memory_increment: false,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+    };
+
+    /// SUPER-CHIP 1.1.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift: true,
+        memory_increment: false,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+    };
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        memory_increment: true,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+    };
+
+    /// Names accepted by [`Quirks::set`].
+    pub const NAMES: [&'static str; 6] = [
+        "shift",
+        "memory-increment",
+        "jump",
+        "vf-reset",
+        "clipping",
+        "display-wait",
+    ];
+
+    /// Looks up a preset by name: `vip`, `chip48`, `schip` or `xochip`.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "chip8" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+
+    /// Reads a single quirk by name, `None` if there is no such quirk.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name.to_lowercase().as_str() {
+            "shift" => Some(self.shift),
+            "memory-increment" => Some(self.memory_increment),
+            "jump" => Some(self.jump),
+            "vf-reset" => Some(self.vf_reset),
+            "clipping" => Some(self.clipping),
+            "display-wait" => Some(self.display_wait),
+            _ => None,
+        }
+    }
+
+    /// Overrides a single quirk by name, returns `false` if there is no such quirk.
+    pub fn set(&mut self, name: &str, enabled: bool) -> bool {
+        let quirk = match name.to_lowercase().as_str() {
+            "shift" => &mut self.shift,
+            "memory-increment" => &mut self.memory_increment,
+            "jump" => &mut self.jump,
+            "vf-reset" => &mut self.vf_reset,
+            "clipping" => &mut self.clipping,
+            "display-wait" => &mut self.display_wait,
+            _ => return false,
+        };
+
+        *quirk = enabled;
+
+        true
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::COSMAC_VIP
+    }
+}
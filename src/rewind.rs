@@ -0,0 +1,270 @@
+use crate::machine::Chip8;
+use crate::scheduler::FRAME_RATE;
+use std::collections::VecDeque;
+
+/// The last frames of the machine, to play it backwards.
+///
+/// Only the newest snapshot is kept whole. Each older frame is stored as the difference
+/// with the frame after it: the XOR of the two save states, with the runs of zeros
+/// (the bytes that didn't change) compressed away. A frame usually costs a few bytes.
+pub struct Rewind {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    /// The oldest frame first.
+    deltas: VecDeque<Delta>,
+}
+
+/// How to go from a snapshot back to the one before it.
+struct Delta {
+    /// Length of the older snapshot.
+    len: usize,
+    /// Pairs of a run of unchanged bytes and a run of changed bytes, see `compress`.
+    data: Vec<u8>,
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` frames.
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Keeps the last `seconds` of emulated time.
+    pub fn with_seconds(seconds: usize) -> Rewind {
+        Rewind::new(seconds.saturating_mul(FRAME_RATE as usize))
+    }
+
+    /// Records the current state, call it once per frame.
+    pub fn push(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+
+        if let Some(previous) = self.newest.take() {
+            self.deltas.push_back(Delta {
+                len: previous.len(),
+                data: compress(&xor(&state, &previous)),
+            });
+        }
+        self.newest = Some(state);
+
+        while self.deltas.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Puts `chip8` back one frame, returns `false` when there is nothing left to rewind.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> bool {
+        let (delta, newest) = match (self.deltas.pop_back(), self.newest.as_mut()) {
+            (Some(delta), Some(newest)) => (delta, newest),
+            _ => return false,
+        };
+
+        let mut older = xor(newest, &decompress(&delta.data));
+        older.truncate(delta.len);
+        *newest = older;
+
+        // The states come from `save_state`, they always load.
+        chip8.load_state(newest).is_ok()
+    }
+
+    /// How many frames can be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    /// Approximate memory used by the snapshots, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, Vec::len);
+
+        newest + self.deltas.iter().map(|d| d.data.len()).sum::<usize>()
+    }
+}
+
+/// XORs `a` and `b`, the shorter one is padded with zeros.
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let len = a.len().max(b.len());
+
+    (0..len)
+        .map(|i| a.get(i).unwrap_or(&0) ^ b.get(i).unwrap_or(&0))
+        .collect()
+}
+
+/// Encodes `data` as pairs of `varint(zeros) varint(len) bytes[len]`.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|b| **b == 0).count();
+        i += zeros;
+        let len = data[i..].iter().take_while(|b| **b != 0).count();
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, len);
+        out.extend_from_slice(&data[i..i + len]);
+        i += len;
+    }
+
+    out
+}
+
+fn decompress(mut data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    while !data.is_empty() {
+        let zeros = read_varint(&mut data);
+        let len = read_varint(&mut data).min(data.len());
+
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&data[..len]);
+        data = &data[len..];
+    }
+
+    out
+}
+
+/// 7 bits per byte, the high bit is set on all but the last byte.
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    while let Some((byte, rest)) = data.split_first() {
+        *data = rest;
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Rng, RngKind};
+
+    fn round_trip(data: &[u8]) {
+        let compressed = compress(data);
+        assert_eq!(decompress(&compressed), data, "{:?}", compressed);
+    }
+
+    #[test]
+    fn compress_round_trips() {
+        let mut rng = Rng::new(RngKind::Xorshift, 5);
+        let random: Vec<u8> = (0..5000).map(|_| rng.next_byte(&[])).collect();
+        // Mostly zeros, with changes here and there like a real delta.
+        let sparse: Vec<u8> = random
+            .iter()
+            .map(|b| if *b < 0xF0 { 0 } else { *b })
+            .collect();
+
+        round_trip(&[]);
+        round_trip(&[0; 4096]);
+        round_trip(&random);
+        round_trip(&sparse);
+        round_trip(&[1, 0, 0, 2, 3, 0]);
+    }
+
+    #[test]
+    fn long_runs_cross_the_varint_boundary() {
+        for len in [127, 128, 129, 300, 16383, 16384, 70000] {
+            let mut data = vec![0; len];
+            data.extend(vec![7; len]);
+            data.extend(vec![0; len]);
+            round_trip(&data);
+        }
+
+        let mut out = Vec::new();
+        write_varint(&mut out, 127);
+        write_varint(&mut out, 128);
+        write_varint(&mut out, 300);
+        assert_eq!(out, [0x7F, 0x80, 0x01, 0xAC, 0x02]);
+
+        let mut data = &out[..];
+        assert_eq!(read_varint(&mut data), 127);
+        assert_eq!(read_varint(&mut data), 128);
+        assert_eq!(read_varint(&mut data), 300);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn empty_delta() {
+        // Nothing changed between two frames.
+        let state = [1, 2, 3];
+        let delta = compress(&xor(&state, &state));
+        assert_eq!(delta, [3, 0]);
+        assert_eq!(xor(&state, &decompress(&delta)), state);
+    }
+
+    /// A machine that changes every frame: `ADD V0, 1; JP $200`.
+    fn counting() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+        chip8
+    }
+
+    #[test]
+    fn rewinds_to_the_first_frame() {
+        let mut chip8 = counting();
+        let mut rewind = Rewind::new(100);
+        let mut states = Vec::new();
+
+        for _ in 0..10 {
+            rewind.push(&chip8);
+            states.push(chip8.save_state());
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(rewind.len(), 9);
+
+        for state in states.iter().rev().skip(1) {
+            assert!(rewind.rewind(&mut chip8));
+            assert_eq!(&chip8.save_state(), state);
+        }
+        assert_eq!(chip8.save_state(), states[0]);
+        assert!(!rewind.rewind(&mut chip8));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_frames_at_capacity() {
+        let mut chip8 = counting();
+        let mut rewind = Rewind::new(3);
+        let mut states = Vec::new();
+
+        for _ in 0..6 {
+            rewind.push(&chip8);
+            states.push(chip8.save_state());
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(rewind.len(), 3);
+
+        for _ in 0..3 {
+            assert!(rewind.rewind(&mut chip8));
+        }
+        assert_eq!(chip8.save_state(), states[2]);
+        assert!(!rewind.rewind(&mut chip8));
+    }
+}
@@ -0,0 +1,215 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The page of the VIP interpreter that its random routine reads.
+const VIP_PAGE: usize = 0x100;
+
+/// The algorithm behind `Cxkk`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RngKind {
+    /// xorshift64*, fast and with good statistical quality.
+    #[default]
+    Xorshift,
+    /// The routine of the COSMAC VIP interpreter. Its 16 bit state, `R9` on the VIP,
+    /// goes up by one every frame and on every call. The low byte picks a byte of the
+    /// interpreter page, `0x100..0x200`, that is mixed into the high byte.
+    ///
+    /// The VIP kept its interpreter code in that page, here it holds the fonts, so the
+    /// numbers have the same short cycles and patterns but not the same values.
+    CosmacVip,
+}
+
+impl RngKind {
+    /// Looks up an algorithm by name: `xorshift` or `vip`.
+    pub fn from_name(name: &str) -> Option<RngKind> {
+        match name.to_lowercase().as_str() {
+            "xorshift" | "modern" => Some(RngKind::Xorshift),
+            "vip" | "cosmac-vip" => Some(RngKind::CosmacVip),
+            _ => None,
+        }
+    }
+}
+
+/// The random number generator of the machine. The same seed always gives the same
+/// sequence, so runs can be reproduced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    kind: RngKind,
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(kind: RngKind, seed: u64) -> Rng {
+        let state = match kind {
+            // xorshift gets stuck on 0, the seed is spread with SplitMix64 first.
+            RngKind::Xorshift => splitmix64(seed).max(1),
+            RngKind::CosmacVip => seed & 0xffff,
+        };
+
+        Rng { kind, seed, state }
+    }
+
+    /// Seeds the generator from the clock, [`Rng::seed`] tells which seed was used.
+    pub fn from_time(kind: RngKind) -> Rng {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Rng::new(kind, seed)
+    }
+
+    pub fn kind(&self) -> RngKind {
+        self.kind
+    }
+
+    /// The seed the generator started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The current position in the sequence.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Continues a sequence from a saved [`Rng::state`].
+    pub fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+
+    /// Advances the state once per frame, like the interrupt routine of the VIP. The
+    /// other generators only move when they're used.
+    pub fn tick(&mut self) {
+        if self.kind == RngKind::CosmacVip {
+            self.state = (self.state + 1) & 0xffff;
+        }
+    }
+
+    /// The next random byte, `mem` is the memory of the machine, which the VIP routine
+    /// reads.
+    pub fn next_byte(&mut self, mem: &[u8]) -> u8 {
+        match self.kind {
+            RngKind::Xorshift => {
+                let mut x = self.state;
+                x ^= x >> 12;
+                x ^= x << 25;
+                x ^= x >> 27;
+                self.state = x;
+
+                (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            }
+            RngKind::CosmacVip => {
+                // INC R9; add the byte at 01xx, xx being R9.0, to R9.1; shift the sum
+                // right with the carry coming in on the left, add the sum again.
+                let state = (self.state + 1) & 0xffff;
+                let (low, high) = (state as u8, (state >> 8) as u8);
+                let byte = mem.get(VIP_PAGE + low as usize).copied().unwrap_or(0);
+
+                let (sum, carry) = high.overflowing_add(byte);
+                let value = ((carry as u8) << 7 | sum >> 1).wrapping_add(sum);
+                self.state = (value as u64) << 8 | low as u64;
+
+                value
+            }
+        }
+    }
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::machine::Chip8;
+
+    fn sequence(rng: &mut Rng, mem: &[u8], len: usize) -> Vec<u8> {
+        (0..len).map(|_| rng.next_byte(mem)).collect()
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mem: Vec<u8> = (0..0x1000).map(|i| (i * 7 + 3) as u8).collect();
+
+        for kind in [RngKind::Xorshift, RngKind::CosmacVip] {
+            let a = sequence(&mut Rng::new(kind, 42), &mem, 64);
+            let b = sequence(&mut Rng::new(kind, 42), &mem, 64);
+            let c = sequence(&mut Rng::new(kind, 43), &mem, 64);
+
+            assert_eq!(a, b, "{:?}", kind);
+            assert_ne!(a, c, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn vip_routine() {
+        let mut mem = vec![0; 0x1000];
+        mem[0x101] = 0xF0;
+
+        // R9 = 2001, 20 + F0 = 10 with a carry, 88 + 10 = 98.
+        let mut rng = Rng::new(RngKind::CosmacVip, 0x2000);
+        assert_eq!(rng.next_byte(&mem), 0x98);
+        assert_eq!(rng.state(), 0x9801);
+
+        // R9 = 9802, 98 + 00 = 98, 4C + 98 = E4.
+        assert_eq!(rng.next_byte(&mem), 0xE4);
+        assert_eq!(rng.state(), 0xE402);
+
+        // INC R9 is 16 bit: R9 = 1300, 13 + 00 = 13, 09 + 13 = 1C.
+        let mut rng = Rng::new(RngKind::CosmacVip, 0x12FF);
+        assert_eq!(rng.next_byte(&mem), 0x1C);
+        assert_eq!(rng.state(), 0x1C00);
+    }
+
+    #[test]
+    fn only_the_vip_routine_ticks() {
+        let mut vip = Rng::new(RngKind::CosmacVip, 0xFFFF);
+        vip.tick();
+        assert_eq!(vip.state(), 0);
+
+        let mut xorshift = Rng::new(RngKind::Xorshift, 1);
+        let state = xorshift.state();
+        xorshift.tick();
+        assert_eq!(xorshift.state(), state);
+    }
+
+    /// `V0` after each of the next frames of a ROM that keeps drawing random numbers.
+    fn frames(chip8: &mut Chip8) -> Vec<u8> {
+        (0..8)
+            .map(|_| {
+                chip8.run_frame().unwrap();
+                chip8.cpu().V[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn state_round_trips_through_a_save_state() {
+        for kind in [RngKind::Xorshift, RngKind::CosmacVip] {
+            let mut chip8 = Chip8::with_config(Config {
+                seed: Some(1234),
+                rng: kind,
+                ..Config::default()
+            });
+            // RND V0, #$FF; JP $200
+            chip8.load_rom(&[0xC0, 0xFF, 0x12, 0x00]).unwrap();
+            chip8.run_frame().unwrap();
+
+            let state = chip8.save_state();
+            let expected = frames(&mut chip8);
+
+            let mut restored = Chip8::new();
+            restored.load_state(&state).unwrap();
+            assert_eq!(restored.cpu().rng.kind(), kind);
+            assert_eq!(restored.cpu().rng.seed(), 1234);
+            assert_eq!(frames(&mut restored), expected, "{:?}", kind);
+        }
+    }
+}
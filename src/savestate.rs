@@ -0,0 +1,619 @@
+use crate::config::{Config, Variant};
+use crate::cpu::CpuState;
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::keypad::Keypad;
+use crate::machine::Chip8;
+use crate::quirks::Quirks;
+use crate::rng::{Rng, RngKind};
+use crate::stack::{CallStack, StackConfig};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The version written by this emulator, states of this version or older can be loaded.
+pub const SAVE_STATE_VERSION: u16 = 1;
+
+// A state starts with the magic and the format version, followed by chunks: a 4 bytes
+// id, the length of the data as a `u32` and the data. All the numbers are little endian.
+// Chunks this version doesn't know are skipped and missing optional chunks get their
+// default value, so new fields can be added without breaking older states.
+const MAGIC: &[u8; 4] = b"C8ST";
+
+/// Why a save state couldn't be loaded or written.
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    /// The data doesn't start with the save state magic.
+    NotASaveState,
+    /// The state was written by a newer version of the emulator.
+    UnsupportedVersion(u16),
+    /// A chunk is missing or truncated.
+    Corrupt(&'static str),
+    /// The state describes a machine that can't be built, like one without room for
+    /// the fonts.
+    Machine(Chip8Error),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io(e) => write!(f, "{}", e),
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is newer than the supported version {}",
+                version, SAVE_STATE_VERSION
+            ),
+            SaveStateError::Corrupt(what) => write!(f, "corrupt save state: {}", what),
+            SaveStateError::Machine(e) => write!(f, "invalid machine in save state: {}", e),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(e: io::Error) -> SaveStateError {
+        SaveStateError::Io(e)
+    }
+}
+
+/// Everything a save state restores.
+pub(crate) struct Snapshot {
+    pub cpu: CpuState,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub exited: bool,
+    pub cycles: usize,
+}
+
+pub(crate) fn encode(cpu: &CpuState, config: &Config, exited: bool, cycles: usize) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.bytes(MAGIC);
+    w.u16(SAVE_STATE_VERSION);
+
+    w.chunk(b"MACH", |w| {
+        w.u8(config.variant as u8);
+        w.u8(quirk_bits(config.quirks));
+        w.u8(exited as u8);
+        w.u32(cycles as u32);
+    });
+
+    w.chunk(b"CPU ", |w| {
+        w.u32(cpu.pc as u32);
+        w.u16(cpu.I);
+        w.u8(cpu.V.len() as u8);
+        w.bytes(&cpu.V);
+        w.u8(cpu.delay);
+        w.u8(cpu.sound);
+        w.bytes(&cpu.rpl);
+        w.u8(cpu.waiting_key.unwrap_or(0xff));
+    });
+
+    w.chunk(b"MEM ", |w| w.bytes(&cpu.mem));
+
+    w.chunk(b"STCK", |w| {
+        let config = cpu.stack.config();
+        w.optional(config.depth);
+        w.optional(config.ram_base);
+        w.u16(cpu.stack.depth() as u16);
+        for frame in cpu.stack.frames() {
+            w.u16(*frame);
+        }
+    });
+
+    w.chunk(b"DISP", |w| {
+        w.u8(cpu.display.is_hires() as u8);
+        w.u8(cpu.display.selected_planes());
+        w.bytes(cpu.display.planes());
+    });
+
+    w.chunk(b"KEYS", |w| w.u16(cpu.keypad.down_mask()));
+
+    w.chunk(b"RNG ", |w| {
+        w.u8(cpu.rng.kind() as u8);
+        w.u64(cpu.rng.seed());
+        w.u64(cpu.rng.state());
+    });
+
+    w.chunk(b"XOCH", |w| {
+        match cpu.audio_pattern {
+            Some(pattern) => {
+                w.u8(1);
+                w.bytes(&pattern);
+            }
+            None => w.u8(0),
+        }
+        w.u8(cpu.pitch);
+    });
+
+    w.out
+}
+
+/// Reads a state, `config` provides what the state doesn't store, like the palette.
+pub(crate) fn decode(data: &[u8], config: &Config) -> Result<Snapshot, SaveStateError> {
+    if data.len() < 6 || &data[..4] != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version > SAVE_STATE_VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+
+    let chunks = split_chunks(&data[6..])?;
+    let chunk = |id: &[u8; 4]| {
+        chunks
+            .iter()
+            .find(|(i, _)| i == id)
+            .map(|(_, c)| Reader::new(c))
+    };
+    let required = |id: &'static [u8; 4], what| chunk(id).ok_or(SaveStateError::Corrupt(what));
+
+    let mut mach = required(b"MACH", "missing machine chunk")?;
+    let variant = match mach.u8()? {
+        0 => Variant::Chip8,
+        1 => Variant::SuperChip,
+        2 => Variant::XoChip,
+        _ => return Err(SaveStateError::Corrupt("unknown variant")),
+    };
+    let quirks = quirks_from_bits(mach.u8()?);
+    let exited = mach.u8()? != 0;
+    let cycles = mach.u32()? as usize;
+
+    let mem = required(b"MEM ", "missing memory chunk")?.rest().to_vec();
+
+    let config = Config {
+        variant,
+        quirks,
+        memory_size: mem.len(),
+        ..config.clone()
+    };
+    // Starts from a reset machine, then overwrites what the state has.
+    let mut cpu = CpuState::new(&[], &config).map_err(SaveStateError::Machine)?;
+    cpu.mem = mem;
+
+    let mut r = required(b"CPU ", "missing cpu chunk")?;
+    cpu.pc = r.u32()? as usize;
+    cpu.I = r.u16()?;
+    let count = r.u8()? as usize;
+    let registers = r.take(count)?;
+    let len = count.min(cpu.V.len());
+    cpu.V[..len].copy_from_slice(&registers[..len]);
+    cpu.delay = r.u8()?;
+    cpu.sound = r.u8()?;
+    cpu.rpl.copy_from_slice(r.take(16)?);
+    cpu.waiting_key = match r.u8()? {
+        0xff => None,
+        key => Some(key & 0xf),
+    };
+
+    if let Some(mut r) = chunk(b"STCK") {
+        let stack_config = StackConfig {
+            depth: r.optional()?,
+            ram_base: r.optional()?,
+        };
+        let depth = r.u16()? as usize;
+        if stack_config.depth.is_some_and(|max| depth > max) {
+            return Err(SaveStateError::Corrupt("stack deeper than its limit"));
+        }
+        // `RET` reads the return addresses back from there.
+        if stack_config
+            .ram_base
+            .is_some_and(|base| base + 2 * depth > cpu.mem.len())
+        {
+            return Err(SaveStateError::Corrupt("stack past the end of memory"));
+        }
+        let frames = (0..depth).map(|_| r.u16()).collect::<Result<_, _>>()?;
+        cpu.stack = CallStack::with_frames(stack_config, frames);
+    }
+
+    if let Some(mut r) = chunk(b"DISP") {
+        let hires = r.u8()? != 0;
+        let selected_planes = r.u8()?;
+        let mut display = Display::new();
+        if !display.restore(hires, selected_planes, r.rest().to_vec()) {
+            return Err(SaveStateError::Corrupt("bad screen size"));
+        }
+        display.set_palette(config.palette);
+        cpu.display = display;
+    }
+
+    if let Some(mut r) = chunk(b"KEYS") {
+        cpu.keypad = Keypad::from_down_mask(r.u16()?);
+    }
+
+    if let Some(mut r) = chunk(b"RNG ") {
+        let kind = match r.u8()? {
+            1 => RngKind::CosmacVip,
+            _ => RngKind::Xorshift,
+        };
+        let mut rng = Rng::new(kind, r.u64()?);
+        rng.set_state(r.u64()?);
+        cpu.rng = rng;
+    }
+
+    if let Some(mut r) = chunk(b"XOCH") {
+        cpu.audio_pattern = match r.u8()? {
+            0 => None,
+            _ => {
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(r.take(16)?);
+                Some(pattern)
+            }
+        };
+        cpu.pitch = r.u8()?;
+    }
+
+    Ok(Snapshot {
+        cpu,
+        variant,
+        quirks,
+        exited,
+        cycles,
+    })
+}
+
+/// The quirks, one bit each in the order of [`Quirks::NAMES`].
+fn quirk_bits(quirks: Quirks) -> u8 {
+    [
+        quirks.shift,
+        quirks.memory_increment,
+        quirks.jump,
+        quirks.vf_reset,
+        quirks.clipping,
+        quirks.display_wait,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (i, on)| bits | ((*on as u8) << i))
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let mut quirks = Quirks::default();
+
+    for (i, name) in Quirks::NAMES.iter().enumerate() {
+        quirks.set(name, bits & (1 << i) != 0);
+    }
+
+    quirks
+}
+
+/// The id and the data of a chunk.
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+fn split_chunks(mut data: &[u8]) -> Result<Vec<Chunk<'_>>, SaveStateError> {
+    let mut chunks = Vec::new();
+
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err(SaveStateError::Corrupt("truncated chunk header"));
+        }
+
+        let mut id = [0; 4];
+        id.copy_from_slice(&data[..4]);
+        let len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+
+        let body = data
+            .get(8..8 + len)
+            .ok_or(SaveStateError::Corrupt("truncated chunk"))?;
+        chunks.push((id, body));
+        data = &data[8 + len..];
+    }
+
+    Ok(chunks)
+}
+
+#[derive(Default)]
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn chunk<F: FnOnce(&mut Writer)>(&mut self, id: &[u8; 4], write: F) {
+        let mut body = Writer::default();
+        write(&mut body);
+
+        self.bytes(id);
+        self.u32(body.out.len() as u32);
+        self.bytes(&body.out);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.out.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn optional(&mut self, value: Option<usize>) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                self.u32(value as u32);
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Corrupt("truncated chunk data"));
+        }
+
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        Ok(taken)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn optional(&mut self) -> Result<Option<usize>, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.u32()? as usize)),
+        }
+    }
+}
+
+/// Numbered save state files for one ROM, `<dir>/<rom>.<slot>.c8s`.
+#[derive(Clone, Debug)]
+pub struct SaveSlots {
+    dir: PathBuf,
+    rom: String,
+}
+
+impl SaveSlots {
+    /// The slots of the ROM named `rom`, like `game_sub.ch8`, stored in `dir`.
+    pub fn new<P: Into<PathBuf>>(dir: P, rom: &str) -> SaveSlots {
+        SaveSlots {
+            dir: dir.into(),
+            rom: rom.to_string(),
+        }
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("{}.{}.c8s", self.rom, slot))
+    }
+
+    /// Writes the state of `chip8` to `slot`, creating the directory if needed.
+    pub fn save(&self, slot: u8, chip8: &Chip8) -> Result<(), SaveStateError> {
+        fs::create_dir_all(&self.dir)?;
+
+        // A crash while writing doesn't destroy the previous state of the slot.
+        let path = self.path(slot);
+        let tmp = path.with_extension("c8s.tmp");
+        fs::write(&tmp, chip8.save_state())?;
+        fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Restores `chip8` from `slot`, it's left untouched on errors.
+    pub fn load(&self, slot: u8, chip8: &mut Chip8) -> Result<(), SaveStateError> {
+        let data = fs::read(self.path(slot))?;
+
+        chip8.load_state(&data)
+    }
+
+    /// Whether something was saved in `slot`.
+    pub fn exists(&self, slot: u8) -> bool {
+        self.path(slot).is_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A machine inside a subroutine, with its stack in memory like the COSMAC VIP.
+    fn running() -> Chip8 {
+        let config = Config {
+            stack: StackConfig::COSMAC_VIP,
+            seed: Some(7),
+            ..Config::default()
+        };
+        let mut chip8 = Chip8::with_config(config);
+        // CALL $206; JP $202; DW 0; LD V5, #$42; JP $208
+        let rom = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x65, 0x42, 0x12, 0x08];
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_frame().unwrap();
+
+        chip8
+    }
+
+    /// `state` with the body of the chunk `id` replaced.
+    fn with_chunk(state: &[u8], id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(&state[..6]);
+        for (chunk_id, chunk) in split_chunks(&state[6..]).unwrap() {
+            let data = if &chunk_id == id { body } else { chunk };
+            w.chunk(&chunk_id, |w| w.bytes(data));
+        }
+
+        w.out
+    }
+
+    fn stack_chunk(config: StackConfig, frames: &[u16]) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.optional(config.depth);
+        w.optional(config.ram_base);
+        w.u16(frames.len() as u16);
+        for frame in frames {
+            w.u16(*frame);
+        }
+
+        w.out
+    }
+
+    /// What the state is missing or has wrong, when it's corrupt.
+    fn corrupt(result: Result<(), SaveStateError>) -> Option<&'static str> {
+        match result {
+            Err(SaveStateError::Corrupt(what)) => Some(what),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let chip8 = running();
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.cpu().pc, 0x208);
+        assert_eq!(restored.cpu().V[5], 0x42);
+        assert_eq!(restored.cpu().stack.frames(), &[0x202]);
+        assert_eq!(restored.cpu().stack.config(), StackConfig::COSMAC_VIP);
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut state = running().save_state();
+        state[3] = b'X';
+
+        assert!(matches!(
+            Chip8::new().load_state(&state),
+            Err(SaveStateError::NotASaveState)
+        ));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut state = running().save_state();
+        state[4..6].copy_from_slice(&(SAVE_STATE_VERSION + 1).to_le_bytes());
+
+        assert!(matches!(
+            Chip8::new().load_state(&state),
+            Err(SaveStateError::UnsupportedVersion(v)) if v == SAVE_STATE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_memory_smaller_than_the_program_area() {
+        let state = with_chunk(&running().save_state(), b"MEM ", &[0; 0x100]);
+
+        assert!(matches!(
+            Chip8::new().load_state(&state),
+            Err(SaveStateError::Machine(Chip8Error::MemoryTooSmall {
+                size: 0x100,
+                min: 0x200
+            }))
+        ));
+    }
+
+    #[test]
+    fn rejects_stacks_past_the_end_of_memory() {
+        let config = StackConfig {
+            depth: None,
+            ram_base: Some(0xFFE),
+        };
+        let stack = stack_chunk(config, &[0x202, 0x204]);
+        let state = with_chunk(&running().save_state(), b"STCK", &stack);
+
+        assert_eq!(
+            corrupt(Chip8::new().load_state(&state)),
+            Some("stack past the end of memory")
+        );
+    }
+
+    #[test]
+    fn rejects_stacks_deeper_than_their_limit() {
+        let stack = stack_chunk(StackConfig::MODERN, &[0x202; 17]);
+        let state = with_chunk(&running().save_state(), b"STCK", &stack);
+
+        assert_eq!(
+            corrupt(Chip8::new().load_state(&state)),
+            Some("stack deeper than its limit")
+        );
+    }
+
+    #[test]
+    fn truncated_states_fail_without_panicking() {
+        let state = running().save_state();
+        let chunks = split_chunks(&state[6..]).unwrap();
+
+        // Where each chunk ends, and the first cut with the machine, cpu and memory.
+        let ends: Vec<usize> = chunks
+            .iter()
+            .scan(6, |end, (_, data)| {
+                *end += 8 + data.len();
+                Some(*end)
+            })
+            .collect();
+        let mem = chunks.iter().position(|(id, _)| id == b"MEM ").unwrap();
+        assert!(chunks[..mem].iter().any(|(id, _)| id == b"CPU "));
+
+        let mut chip8 = Chip8::new();
+        for len in 0..state.len() {
+            let result = chip8.load_state(&state[..len]);
+            let start = ends.iter().rev().find(|end| **end <= len).unwrap_or(&6);
+
+            if len < 6 {
+                assert!(matches!(result, Err(SaveStateError::NotASaveState)));
+            } else if len - start >= 8 {
+                assert_eq!(corrupt(result), Some("truncated chunk"));
+            } else if len > *start {
+                assert_eq!(corrupt(result), Some("truncated chunk header"));
+            } else if len == 6 {
+                assert_eq!(corrupt(result), Some("missing machine chunk"));
+            } else if len < ends[mem] {
+                assert_eq!(corrupt(result), Some("missing memory chunk"));
+            } else {
+                // The chunks after the memory are optional.
+                assert!(result.is_ok(), "cut after {} bytes", len);
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The rate of the timers and of the original display.
+pub const FRAME_RATE: u32 = 60;
+
+/// Keeps a frontend loop running at a steady frame rate by sleeping between frames.
+///
+/// ```no_run
+/// let mut chip8 = chip8::Chip8::new();
+/// let mut limiter = chip8::FrameLimiter::new(chip8::FRAME_RATE);
+///
+/// loop {
+///     chip8.run_frame().unwrap();
+///     limiter.wait();
+/// }
+/// ```
+pub struct FrameLimiter {
+    frame: Duration,
+    next: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(fps: u32) -> FrameLimiter {
+        FrameLimiter {
+            frame: Duration::from_secs(1) / fps,
+            next: Instant::now(),
+        }
+    }
+
+    /// Sleeps until it's time for the next frame.
+    ///
+    /// When the loop falls behind (the window was dragged, the host is busy...)
+    /// the missed frames are dropped instead of being run all at once.
+    pub fn wait(&mut self) {
+        self.next += self.frame;
+
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        } else {
+            self.next = now;
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::machine::Chip8;
+use std::error::Error;
+use std::fmt;
+
+/// Key presses to replay frame by frame, to drive a ROM without a keyboard.
+///
+/// ```text
+/// # frame  action   keys
+/// 0        press    5
+/// 10       release  5
+/// 60       tap      A F
+/// ```
+///
+/// The actions happen before the frame with that number runs, the first frame is 0.
+/// `press` and `release` change the keys, `tap` holds them for one frame. Keys are hex
+/// digits.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InputScript {
+    /// Sorted by frame, in file order within a frame.
+    events: Vec<(usize, u8, bool)>,
+}
+
+/// A syntax error in an input script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "input script line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<InputScript, ScriptError> {
+        let mut events = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| ScriptError {
+                line: i + 1,
+                message,
+            };
+
+            let mut words = line.split('#').next().unwrap_or("").split_whitespace();
+            let (frame, action) = match (words.next(), words.next()) {
+                (None, _) => continue,
+                (Some(frame), Some(action)) => (frame, action),
+                (Some(_), None) => {
+                    return Err(error(format!(
+                        "expected `frame action keys`, got `{}`",
+                        line
+                    )))
+                }
+            };
+
+            let frame: usize = frame
+                .parse()
+                .map_err(|_| error(format!("`{}` is not a frame number", frame)))?;
+
+            let keys = words
+                .map(|key| match u8::from_str_radix(key, 16) {
+                    Ok(k) if k <= 0xF => Ok(k),
+                    _ => Err(error(format!("`{}` is not a keypad key", key))),
+                })
+                .collect::<Result<Vec<u8>, ScriptError>>()?;
+            if keys.is_empty() {
+                return Err(error(format!("`{}` needs at least one key", action)));
+            }
+
+            for key in keys {
+                match action {
+                    "press" => events.push((frame, key, true)),
+                    "release" => events.push((frame, key, false)),
+                    "tap" => {
+                        let next = frame
+                            .checked_add(1)
+                            .ok_or_else(|| error(format!("can't tap at frame {}", frame)))?;
+                        events.push((frame, key, true));
+                        events.push((next, key, false));
+                    }
+                    _ => return Err(error(format!("unknown action `{}`", action))),
+                }
+            }
+        }
+
+        // Stable, the order of the file is kept within a frame.
+        events.sort_by_key(|(frame, _, _)| *frame);
+
+        Ok(InputScript { events })
+    }
+
+    /// Presses and releases the keys of `frame`, call it before running that frame.
+    pub fn apply(&self, frame: usize, chip8: &mut Chip8) {
+        let start = self.events.partition_point(|(f, _, _)| *f < frame);
+
+        for (_, key, pressed) in self.events[start..]
+            .iter()
+            .take_while(|(f, _, _)| *f == frame)
+        {
+            chip8.set_key(*key, *pressed);
+        }
+    }
+
+    /// The frame of the last action, `None` for an empty script.
+    pub fn last_frame(&self) -> Option<usize> {
+        self.events.last().map(|(frame, _, _)| *frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> (usize, String) {
+        let e = InputScript::parse(text).unwrap_err();
+        (e.line, e.message)
+    }
+
+    #[test]
+    fn parses_and_sorts_the_events() {
+        let script = InputScript::parse(
+            "# frame action keys\n\
+             \n\
+             10 release 5\n\
+             0  press   5 # hold\n\
+             60 tap     a F\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            script.events,
+            [
+                (0, 5, true),
+                (10, 5, false),
+                (60, 0xA, true),
+                (60, 0xF, true),
+                (61, 0xA, false),
+                (61, 0xF, false),
+            ]
+        );
+        assert_eq!(script.last_frame(), Some(61));
+        assert_eq!(InputScript::parse("").unwrap().last_frame(), None);
+    }
+
+    #[test]
+    fn applies_the_events_of_a_frame() {
+        let script = InputScript::parse("0 press 1 2\n2 tap 3\n3 release 1").unwrap();
+        let mut chip8 = Chip8::new();
+        let mut down = Vec::new();
+
+        for frame in 0..5 {
+            script.apply(frame, &mut chip8);
+            down.push(
+                (0..16)
+                    .filter(|k| chip8.keypad().is_down(*k))
+                    .collect::<Vec<u8>>(),
+            );
+        }
+
+        assert_eq!(
+            down,
+            [vec![1, 2], vec![1, 2], vec![1, 2, 3], vec![2], vec![2]]
+        );
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        assert_eq!(
+            parse_error("0 press 1\nten press 1"),
+            (2, "`ten` is not a frame number".to_string())
+        );
+        assert_eq!(
+            parse_error("0 press 10"),
+            (1, "`10` is not a keypad key".to_string())
+        );
+        assert_eq!(
+            parse_error("\n0 hold 1"),
+            (2, "unknown action `hold`".to_string())
+        );
+        assert_eq!(
+            parse_error("0 press"),
+            (1, "`press` needs at least one key".to_string())
+        );
+        assert_eq!(parse_error("0").0, 1);
+    }
+
+    #[test]
+    fn taps_on_the_last_frame_fail() {
+        let text = format!("{} tap 1", usize::MAX);
+
+        assert_eq!(
+            parse_error(&text),
+            (1, format!("can't tap at frame {}", usize::MAX))
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use crate::audio::AudioSink;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// How much sound can be queued, in seconds. When the emulator runs ahead of the sound
+/// card the oldest samples are dropped, so the sound doesn't lag behind the picture.
+const MAX_LATENCY: f32 = 0.1;
+
+/// An [`AudioSink`] that plays on the default output device of the system.
+pub struct Speaker {
+    /// Playing stops when the stream is dropped.
+    _stream: Stream,
+    sample_rate: u32,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl Speaker {
+    /// Opens the default output device.
+    pub fn new() -> io::Result<Speaker> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| io::Error::other("no audio output device"))?;
+
+        let supported = device.default_output_config().map_err(io::Error::other)?;
+        let format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+        let stream = match format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, &queue),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, &queue),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, &queue),
+            _ => {
+                let message = format!("unsupported sample format {}", format);
+                return Err(io::Error::other(message));
+            }
+        }?;
+
+        stream.play().map_err(io::Error::other)?;
+
+        Ok(Speaker {
+            _stream: stream,
+            sample_rate: config.sample_rate.0,
+            queue,
+        })
+    }
+}
+
+/// Plays the queued samples on every channel, silence when the queue runs dry.
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    queue: &Arc<Mutex<VecDeque<f32>>>,
+) -> io::Result<Stream> {
+    let channels = config.channels as usize;
+    let queue = Arc::clone(queue);
+
+    let data_callback = move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+        let mut queue = queue.lock().unwrap();
+
+        for frame in data.chunks_mut(channels) {
+            let sample = T::from_sample(queue.pop_front().unwrap_or(0.0));
+            for out in frame.iter_mut() {
+                *out = sample;
+            }
+        }
+    };
+    let error_callback = |e| eprintln!("audio stream error: {}", e);
+
+    device
+        .build_output_stream(config, data_callback, error_callback, None)
+        .map_err(io::Error::other)
+}
+
+impl AudioSink for Speaker {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+
+        let max = (self.sample_rate as f32 * MAX_LATENCY) as usize;
+        let excess = queue.len().saturating_sub(max);
+        queue.drain(..excess);
+    }
+}
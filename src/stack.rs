@@ -0,0 +1,180 @@
+/// How the return addresses of `CALL` are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackConfig {
+    /// Maximum number of nested calls, `None` for no limit.
+    pub depth: Option<usize>,
+    /// When set, the stack also lives in memory starting at this address, the way the
+    /// original interpreter did it. Some ROMs peek at (or patch) it.
+    pub ram_base: Option<usize>,
+}
+
+impl StackConfig {
+    /// 12 levels stored at `0xEA0`, like the COSMAC VIP.
+    pub const COSMAC_VIP: StackConfig = StackConfig {
+        depth: Some(12),
+        ram_base: Some(0xEA0),
+    };
+
+    /// 16 levels outside of memory, what most modern interpreters do.
+    pub const MODERN: StackConfig = StackConfig {
+        depth: Some(16),
+        ram_base: None,
+    };
+
+    pub const UNLIMITED: StackConfig = StackConfig {
+        depth: None,
+        ram_base: None,
+    };
+}
+
+impl Default for StackConfig {
+    fn default() -> StackConfig {
+        StackConfig::MODERN
+    }
+}
+
+/// The return address stack used by `CALL` and `RET`.
+#[derive(Clone, Debug)]
+pub struct CallStack {
+    frames: Vec<u16>,
+    config: StackConfig,
+}
+
+impl CallStack {
+    pub fn new(config: StackConfig) -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+            config,
+        }
+    }
+
+    /// A stack that already holds `frames`, when restoring a saved state.
+    pub(crate) fn with_frames(config: StackConfig, frames: Vec<u16>) -> CallStack {
+        CallStack { frames, config }
+    }
+
+    /// The return addresses, the innermost call is the last one.
+    pub fn frames(&self) -> &[u16] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn config(&self) -> StackConfig {
+        self.config
+    }
+
+    /// Pushes a return address, returns `false` if the stack is full.
+    pub(crate) fn push(&mut self, addr: u16, mem: &mut [u8]) -> bool {
+        if let Some(depth) = self.config.depth {
+            if self.frames.len() >= depth {
+                return false;
+            }
+        }
+
+        if let Some(base) = self.config.ram_base {
+            let slot = base + 2 * self.frames.len();
+
+            if slot + 1 >= mem.len() {
+                return false;
+            }
+
+            mem[slot] = (addr >> 8) as u8;
+            mem[slot + 1] = (addr & 0xff) as u8;
+        }
+
+        self.frames.push(addr);
+
+        true
+    }
+
+    /// Pops a return address, `None` if the stack is empty.
+    ///
+    /// With the in memory layout the address is read back from memory, so changes made
+    /// by the ROM are honoured.
+    pub(crate) fn pop(&mut self, mem: &[u8]) -> Option<u16> {
+        let addr = self.frames.pop()?;
+
+        match self.config.ram_base {
+            Some(base) => {
+                let slot = base + 2 * self.frames.len();
+                Some(((mem[slot] as u16) << 8) | mem[slot + 1] as u16)
+            }
+            None => Some(addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflows_at_its_depth() {
+        let mut mem = vec![0; 0x1000];
+        let mut stack = CallStack::new(StackConfig::MODERN);
+
+        for i in 0..16 {
+            assert!(stack.push(0x200 + 2 * i, &mut mem));
+        }
+        assert!(!stack.push(0x300, &mut mem));
+        assert_eq!(stack.depth(), 16);
+        assert_eq!(stack.frames()[15], 0x21E);
+
+        let mut unlimited = CallStack::new(StackConfig::UNLIMITED);
+        for _ in 0..1000 {
+            assert!(unlimited.push(0x200, &mut mem));
+        }
+        assert!(mem.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn underflows_when_empty() {
+        let mut mem = vec![0; 0x1000];
+        let mut stack = CallStack::new(StackConfig::COSMAC_VIP);
+
+        assert_eq!(stack.pop(&mem), None);
+        assert!(stack.push(0x202, &mut mem));
+        assert_eq!(stack.pop(&mem), Some(0x202));
+        assert_eq!(stack.pop(&mem), None);
+    }
+
+    #[test]
+    fn vip_stack_lives_at_0xea0() {
+        let mut mem = vec![0; 0x1000];
+        let mut stack = CallStack::new(StackConfig::COSMAC_VIP);
+
+        assert!(stack.push(0x234, &mut mem));
+        assert!(stack.push(0x456, &mut mem));
+        assert_eq!(&mem[0xEA0..0xEA4], &[0x02, 0x34, 0x04, 0x56]);
+
+        // RET reads the address back from memory, patches included.
+        mem[0xEA3] = 0x60;
+        assert_eq!(stack.pop(&mem), Some(0x460));
+        assert_eq!(stack.pop(&mem), Some(0x234));
+
+        for i in 0..12 {
+            assert!(stack.push(0x200 + i, &mut mem));
+        }
+        assert!(!stack.push(0x300, &mut mem));
+        assert_eq!(&mem[0xEB6..0xEB8], &[0x02, 0x0B]);
+        assert_eq!(mem[0xEB8], 0);
+    }
+
+    #[test]
+    fn ram_stacks_stop_at_the_end_of_memory() {
+        let mut mem = vec![0; 0x1000];
+        let config = StackConfig {
+            depth: None,
+            ram_base: Some(0xFFC),
+        };
+        let mut stack = CallStack::new(config);
+
+        assert!(stack.push(0x202, &mut mem));
+        assert!(stack.push(0x204, &mut mem));
+        assert!(!stack.push(0x206, &mut mem));
+        assert_eq!(stack.depth(), 2);
+    }
+}
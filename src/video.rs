@@ -0,0 +1,706 @@
+use crate::display::{Display, Palette, DEFAULT_PALETTE, HIRES_H, HIRES_W};
+use crate::scheduler::FRAME_RATE;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Receives the screen at the end of every frame, see [`Chip8::set_video_sink`].
+///
+/// [`Chip8::set_video_sink`]: crate::Chip8::set_video_sink
+pub trait VideoSink {
+    /// Receives the screen of the frame that just ended, 60 times per emulated second.
+    fn write(&mut self, display: &Display);
+
+    /// Ends the recording and reports the errors `write` couldn't. Frames written
+    /// afterwards are ignored.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// The screen at the size of the high resolution mode times `scale`, one pixel value
+/// (0 to 3) per pixel. Low resolution pixels are doubled so the size never changes.
+fn scaled_frame(display: &Display, scale: usize) -> Vec<u8> {
+    let (w, h) = (display.width(), display.height());
+    let (out_w, out_h) = (HIRES_W * scale, HIRES_H * scale);
+    let (sx, sy) = (out_w / w, out_h / h);
+    let planes = display.planes();
+
+    (0..out_w * out_h)
+        .map(|i| planes[(i / out_w / sy) * w + i % out_w / sx] & 3)
+        .collect()
+}
+
+/// The time frame `n` starts at, in hundredths of a second.
+fn centiseconds(n: u64) -> u64 {
+    n * 100 / FRAME_RATE as u64
+}
+
+fn rgb(color: u32) -> [u8; 3] {
+    let [_, r, g, b] = color.to_be_bytes();
+    [r, g, b]
+}
+
+/// Records an animated GIF that loops forever, for sharing.
+///
+/// GIF delays are in hundredths of a second and browsers slow down the frames shorter
+/// than 2/100 s, so a frame that changes before that is replaced by the next one.
+/// Frames that don't change are merged. The size is the high resolution screen times
+/// the scale.
+pub struct GifSink<W: Write> {
+    writer: W,
+    scale: usize,
+    /// The palette of the header, frames in other colors get their own.
+    palette: Option<Palette>,
+    /// The frame waiting for its delay to be known, with its palette and start time.
+    pending: Option<(Vec<u8>, Palette, u64)>,
+    /// Frames received so far.
+    frames: u64,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl GifSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, scale: usize) -> io::Result<GifSink<BufWriter<File>>> {
+        Ok(GifSink::new(BufWriter::new(File::create(path)?), scale))
+    }
+}
+
+impl<W: Write> GifSink<W> {
+    /// Every screen pixel is a `scale * scale` square of the image, in high resolution.
+    pub fn new(writer: W, scale: usize) -> GifSink<W> {
+        GifSink {
+            writer,
+            scale: scale.max(1),
+            palette: None,
+            pending: None,
+            frames: 0,
+            error: None,
+            finished: false,
+        }
+    }
+
+    fn size(&self) -> (u16, u16) {
+        ((HIRES_W * self.scale) as u16, (HIRES_H * self.scale) as u16)
+    }
+
+    fn write_header(&mut self, palette: Palette) -> io::Result<()> {
+        let (w, h) = self.size();
+
+        self.writer.write_all(b"GIF89a")?;
+        self.writer.write_all(&w.to_le_bytes())?;
+        self.writer.write_all(&h.to_le_bytes())?;
+        // A global color table of 4 colors, background color 0, square pixels.
+        self.writer.write_all(&[0xf1, 0, 0])?;
+        write_color_table(&mut self.writer, palette)?;
+
+        // Loops forever.
+        self.writer
+            .write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        self.palette = Some(palette);
+
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: &[u8], palette: Palette, delay: u64) -> io::Result<()> {
+        if self.palette.is_none() {
+            self.write_header(palette)?;
+        }
+        let (w, h) = self.size();
+        let delay = delay.min(u16::MAX as u64) as u16;
+
+        // Graphic control extension: keep the previous frame, the delay, no transparency.
+        self.writer.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0, 0])?;
+
+        self.writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.writer.write_all(&w.to_le_bytes())?;
+        self.writer.write_all(&h.to_le_bytes())?;
+        if self.palette == Some(palette) {
+            self.writer.write_all(&[0])?;
+        } else {
+            self.writer.write_all(&[0x81])?;
+            write_color_table(&mut self.writer, palette)?;
+        }
+
+        self.writer.write_all(&[MIN_CODE_SIZE])?;
+        for block in lzw(frame).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])
+    }
+
+    fn push(&mut self, frame: Vec<u8>, palette: Palette) -> io::Result<()> {
+        let now = centiseconds(self.frames);
+        self.frames += 1;
+
+        let (pending, pending_palette, start) = match self.pending.take() {
+            Some(pending) => pending,
+            None => {
+                self.pending = Some((frame, palette, now));
+                return Ok(());
+            }
+        };
+
+        if frame == pending && palette == pending_palette {
+            self.pending = Some((pending, pending_palette, start));
+        } else if now - start < 2 {
+            self.pending = Some((frame, palette, start));
+        } else {
+            self.write_frame(&pending, pending_palette, now - start)?;
+            self.pending = Some((frame, palette, now));
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        let now = centiseconds(self.frames);
+
+        match self.pending.take() {
+            Some((frame, palette, start)) => {
+                self.write_frame(&frame, palette, (now - start).max(2))?
+            }
+            None if self.palette.is_none() => self.write_header(DEFAULT_PALETTE)?,
+            None => {}
+        }
+
+        self.writer.write_all(b";")?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> VideoSink for GifSink<W> {
+    fn write(&mut self, display: &Display) {
+        if self.finished || self.error.is_some() {
+            return;
+        }
+
+        let frame = scaled_frame(display, self.scale);
+        if let Err(e) = self.push(frame, display.palette()) {
+            self.error = Some(e);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        self.end()
+    }
+}
+
+impl<W: Write> Drop for GifSink<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn write_color_table<W: Write>(writer: &mut W, palette: Palette) -> io::Result<()> {
+    for color in palette.iter() {
+        writer.write_all(&rgb(*color))?;
+    }
+
+    Ok(())
+}
+
+/// Bits per pixel value, the smallest code size GIF allows.
+const MIN_CODE_SIZE: u8 = 2;
+
+/// The longest LZW codes of GIF, the table is reset when it has 4096 entries.
+const MAX_CODE: u16 = 1 << 12;
+
+/// Compresses pixel values below 4 with the LZW of GIF.
+fn lzw(data: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    let mut out = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = MIN_CODE_SIZE + 1;
+
+    out.write(clear, size);
+
+    let mut prefix = match data.first() {
+        Some(first) => *first as u16,
+        None => {
+            out.write(end, size);
+            return out.finish();
+        }
+    };
+
+    for value in &data[1..] {
+        if let Some(code) = table.get(&(prefix, *value)) {
+            prefix = *code;
+            continue;
+        }
+
+        out.write(prefix, size);
+
+        if next < MAX_CODE {
+            table.insert((prefix, *value), next);
+            next += 1;
+            // The decoder adds its entries one code late, so the codes get longer once
+            // the entry after the current limit is used.
+            if next > 1 << size && size < 12 {
+                size += 1;
+            }
+        } else {
+            out.write(clear, size);
+            table.clear();
+            next = end + 1;
+            size = MIN_CODE_SIZE + 1;
+        }
+
+        prefix = *value as u16;
+    }
+
+    out.write(prefix, size);
+    if next == 1 << size && size < 12 {
+        size += 1;
+    }
+    out.write(end, size);
+
+    out.finish()
+}
+
+/// Packs codes from the least significant bit, like GIF wants.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    len: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.bits |= (code as u32) << self.len;
+        self.len += size;
+
+        while self.len >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+
+        self.bytes
+    }
+}
+
+/// Records an uncompressed YUV4MPEG2 stream (4:4:4, 60 fps) that video tools like
+/// `ffmpeg` read directly. The size is the high resolution screen times the scale.
+pub struct Y4mSink<W: Write> {
+    writer: W,
+    scale: usize,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl Y4mSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, scale: usize) -> io::Result<Y4mSink<BufWriter<File>>> {
+        Y4mSink::new(BufWriter::new(File::create(path)?), scale)
+    }
+}
+
+impl<W: Write> Y4mSink<W> {
+    /// Writes the stream header to `writer`, the frames follow it.
+    pub fn new(mut writer: W, scale: usize) -> io::Result<Y4mSink<W>> {
+        let scale = scale.max(1);
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+            HIRES_W * scale,
+            HIRES_H * scale,
+            FRAME_RATE
+        )?;
+
+        Ok(Y4mSink {
+            writer,
+            scale,
+            error: None,
+            finished: false,
+        })
+    }
+
+    fn write_frame(&mut self, display: &Display) -> io::Result<()> {
+        // BT.601 in the limited range.
+        let yuv = display.palette().map(|color| {
+            let [r, g, b] = rgb(color).map(|c| c as i32);
+            [
+                ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16,
+                ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128,
+                ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128,
+            ]
+            .map(|c| c as u8)
+        });
+        // The Y, U and V values of the 4 pixel values, the planes follow each other.
+        let planes = [0, 1, 2].map(|plane| yuv.map(|color| color[plane]));
+
+        let frame = scaled_frame(display, self.scale);
+        let mut data = Vec::with_capacity(frame.len() * 3 + 6);
+        data.extend_from_slice(b"FRAME\n");
+        for values in planes.iter() {
+            data.extend(frame.iter().map(|value| values[*value as usize]));
+        }
+
+        self.writer.write_all(&data)
+    }
+}
+
+impl<W: Write> VideoSink for Y4mSink<W> {
+    fn write(&mut self, display: &Display) {
+        if self.finished || self.error.is_some() {
+            return;
+        }
+
+        if let Err(e) = self.write_frame(display) {
+            self.error = Some(e);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+/// Records raw 24 bit RGB frames back to back, without any header. The size is the
+/// high resolution screen times the scale, at 60 fps:
+///
+/// ```text
+/// ffmpeg -f rawvideo -pixel_format rgb24 -video_size 256x128 -framerate 60 -i game.rgb game.mp4
+/// ```
+pub struct RawVideoSink<W: Write> {
+    writer: W,
+    scale: usize,
+    error: Option<io::Error>,
+    finished: bool,
+}
+
+impl RawVideoSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        scale: usize,
+    ) -> io::Result<RawVideoSink<BufWriter<File>>> {
+        Ok(RawVideoSink::new(
+            BufWriter::new(File::create(path)?),
+            scale,
+        ))
+    }
+}
+
+impl<W: Write> RawVideoSink<W> {
+    pub fn new(writer: W, scale: usize) -> RawVideoSink<W> {
+        RawVideoSink {
+            writer,
+            scale: scale.max(1),
+            error: None,
+            finished: false,
+        }
+    }
+}
+
+impl<W: Write> VideoSink for RawVideoSink<W> {
+    fn write(&mut self, display: &Display) {
+        if self.finished || self.error.is_some() {
+            return;
+        }
+
+        let colors = display.palette().map(rgb);
+        let data: Vec<u8> = scaled_frame(display, self.scale)
+            .iter()
+            .flat_map(|value| colors[*value as usize])
+            .collect();
+
+        if let Err(e) = self.writer.write_all(&data) {
+            self.error = Some(e);
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a GIF LZW stream of 2 bit pixels, returns the pixels and the size of
+    /// every code read.
+    fn unlzw(bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let fresh = || -> Vec<Vec<u8>> { (0..6).map(|v| vec![v]).collect() };
+        let mut bits = bytes
+            .iter()
+            .flat_map(|byte| (0..8).map(move |i| (byte >> i) & 1));
+        let mut read = |size: u8| {
+            (0..size).try_fold(0usize, |code, i| {
+                bits.next().map(|bit| code | (bit as usize) << i)
+            })
+        };
+
+        let mut table = fresh();
+        let mut size = 3;
+        let mut prev: Option<Vec<u8>> = None;
+        let (mut data, mut sizes) = (Vec::new(), Vec::new());
+
+        loop {
+            let code = read(size).expect("the stream ends with the end code");
+            sizes.push(size);
+
+            match code {
+                4 => {
+                    table = fresh();
+                    size = 3;
+                    prev = None;
+                    continue;
+                }
+                5 => break,
+                _ => {}
+            }
+
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) if code == table.len() => {
+                    let mut entry = prev.clone();
+                    entry.push(prev[0]);
+                    entry
+                }
+                _ => panic!("code {} is not in the table", code),
+            };
+            data.extend_from_slice(&entry);
+
+            if let Some(mut prev) = prev.take() {
+                if table.len() < MAX_CODE as usize {
+                    prev.push(entry[0]);
+                    table.push(prev);
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            prev = Some(entry);
+        }
+
+        (data, sizes)
+    }
+
+    /// Pixel values 0 to 3 that don't repeat much, so the table fills up quickly.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 7) as u8 & 3
+            })
+            .collect()
+    }
+
+    fn sprite() -> Display {
+        let mut display = Display::new();
+        display.draw_sprite(2, 3, &[0xf0, 0x90, 0xf0], false, false);
+        display
+    }
+
+    /// The data of the sub-blocks starting at `pos`, and the position after them.
+    fn sub_blocks(bytes: &[u8], mut pos: usize) -> (Vec<u8>, usize) {
+        let mut data = Vec::new();
+        loop {
+            let len = bytes[pos] as usize;
+            pos += 1;
+            if len == 0 {
+                return (data, pos);
+            }
+            data.extend_from_slice(&bytes[pos..pos + len]);
+            pos += len;
+        }
+    }
+
+    #[test]
+    fn lzw_matches_a_known_stream() {
+        // The 10x10 example image of "What's in a GIF", with its published encoding.
+        let rows = [
+            "1111122222",
+            "1111122222",
+            "1111122222",
+            "1110000222",
+            "1110000222",
+            "2220000111",
+            "2220000111",
+            "2222211111",
+            "2222211111",
+            "2222211111",
+        ];
+        let data: Vec<u8> = rows.concat().bytes().map(|b| b - b'0').collect();
+        let expected = [
+            0x8c, 0x2d, 0x99, 0x87, 0x2a, 0x1c, 0xdc, 0x33, 0xa0, 0x02, 0x75, 0xec, 0x95, 0xfa,
+            0xa8, 0xde, 0x60, 0x8c, 0x04, 0x91, 0x4c, 0x01,
+        ];
+
+        assert_eq!(lzw(&data), expected);
+        assert_eq!(lzw(&[0]), [0x44, 0x01]);
+        assert_eq!(unlzw(&lzw(&[])), (vec![], vec![3, 3]));
+    }
+
+    #[test]
+    fn lzw_codes_grow_and_reset() {
+        let data = noise(100_000);
+        let (decoded, sizes) = unlzw(&lzw(&data));
+
+        assert_eq!(decoded, data);
+        assert_eq!(sizes.iter().max(), Some(&12));
+
+        // Every reset goes back to 3 bit codes and grows again one bit at a time.
+        let resets: Vec<usize> = (1..sizes.len())
+            .filter(|i| sizes[*i] < sizes[i - 1])
+            .collect();
+        assert!(resets.len() > 2);
+        for pair in resets.windows(2) {
+            let run = &sizes[pair[0]..pair[1]];
+            assert_eq!(run[0], 3);
+            assert!(run.windows(2).all(|w| w[1] == w[0] || w[1] == w[0] + 1));
+            assert_eq!(run[run.len() - 1], 12);
+            // The encoder adds an entry with every code, so the 4090 free entries are
+            // used up one code before the clear.
+            assert_eq!(run.len(), (MAX_CODE as usize - 6) + 1 + 1);
+        }
+    }
+
+    #[test]
+    fn gif_header_frames_and_trailer() {
+        let mut out = Vec::new();
+        {
+            let mut sink = GifSink::new(&mut out, 2);
+            for _ in 0..10 {
+                sink.write(&Display::new());
+            }
+            for _ in 0..10 {
+                sink.write(&sprite());
+            }
+            sink.finish().unwrap();
+        }
+
+        assert_eq!(&out[..6], b"GIF89a");
+        assert_eq!(&out[6..13], &[0, 1, 128, 0, 0xf1, 0, 0]);
+        assert_eq!(&out[13..19], &[0, 0, 0, 0xff, 0xff, 0xff]);
+        assert_eq!(out.last(), Some(&b';'));
+
+        let (mut pos, mut frames, mut delays) = (13 + 12, Vec::new(), Vec::new());
+        loop {
+            match out[pos] {
+                0x21 => {
+                    if out[pos + 1] == 0xf9 {
+                        delays.push(u16::from_le_bytes([out[pos + 4], out[pos + 5]]));
+                    } else {
+                        assert_eq!(&out[pos + 1..pos + 14], b"\xff\x0bNETSCAPE2.0");
+                    }
+                    pos = sub_blocks(&out, pos + 2).1;
+                }
+                0x2c => {
+                    assert_eq!(out[pos + 9], 0);
+                    assert_eq!(out[pos + 10], MIN_CODE_SIZE);
+                    let (data, next) = sub_blocks(&out, pos + 11);
+                    frames.push(unlzw(&data).0);
+                    pos = next;
+                }
+                b';' => break,
+                byte => panic!("unexpected block {:#04x} at {}", byte, pos),
+            }
+        }
+
+        assert_eq!(pos, out.len() - 1);
+        assert_eq!(delays, [16, 33 - 16]);
+        assert_eq!(
+            frames,
+            [scaled_frame(&Display::new(), 2), scaled_frame(&sprite(), 2)]
+        );
+    }
+
+    #[test]
+    fn empty_gifs_are_still_valid() {
+        let mut out = Vec::new();
+        GifSink::new(&mut out, 1).finish().unwrap();
+
+        assert_eq!(&out[..6], b"GIF89a");
+        assert_eq!(out.len(), 13 + 12 + 19 + 1);
+        assert_eq!(out.last(), Some(&b';'));
+    }
+
+    #[test]
+    fn y4m_frames() {
+        let mut out = Vec::new();
+        {
+            let mut sink = Y4mSink::new(&mut out, 1).unwrap();
+            sink.write(&Display::new());
+            sink.write(&sprite());
+            sink.write(&Display::new());
+            sink.finish().unwrap();
+        }
+
+        let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+        assert_eq!(&out[..header.len()], header);
+
+        let plane = HIRES_W * HIRES_H;
+        let frames: Vec<&[u8]> = out[header.len()..].chunks(6 + 3 * plane).collect();
+        assert_eq!(frames.len(), 3);
+
+        for (frame, display) in frames
+            .iter()
+            .zip([Display::new(), sprite(), Display::new()])
+        {
+            assert_eq!(frame.len(), 6 + 3 * plane);
+            assert_eq!(&frame[..6], b"FRAME\n");
+
+            let pixels = scaled_frame(&display, 1);
+            let (y, uv) = frame[6..].split_at(plane);
+            let luma: Vec<u8> = pixels.iter().map(|p| [16, 235][*p as usize]).collect();
+            assert_eq!(y, &luma[..]);
+            assert!(uv.iter().all(|c| *c == 128));
+        }
+    }
+
+    #[test]
+    fn raw_frames_are_rgb() {
+        let mut out = Vec::new();
+        {
+            let mut sink = RawVideoSink::new(&mut out, 2);
+            sink.write(&Display::new());
+            sink.write(&sprite());
+            sink.finish().unwrap();
+        }
+
+        let frame = 3 * HIRES_W * HIRES_H * 4;
+        assert_eq!(out.len(), 2 * frame);
+        assert!(out[..frame].iter().all(|c| *c == 0));
+
+        // The sprite starts at (2, 3), low resolution pixels are 4x4 at scale 2.
+        let lit = (3 * 4 * HIRES_W * 2 + 2 * 4) * 3;
+        assert_eq!(&out[frame + lit..frame + lit + 3], &[0xff; 3]);
+    }
+}
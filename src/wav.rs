@@ -0,0 +1,209 @@
+use crate::audio::AudioSink;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The sample rate of [`WavSink::create`].
+pub const WAV_SAMPLE_RATE: u32 = 44_100;
+
+/// Size of the RIFF header and the `fmt ` chunk, the samples start right after it.
+const HEADER_SIZE: u32 = 44;
+
+/// An [`AudioSink`] that writes a 16 bit mono PCM `.wav` file, to record or test the
+/// sound without a sound card.
+///
+/// The sizes in the header are updated by [`AudioSink::flush`] and when the sink is
+/// dropped.
+pub struct WavSink<W: Write + Seek> {
+    /// Only `None` once `into_inner` took it.
+    writer: Option<W>,
+    sample_rate: u32,
+    /// Bytes of samples written so far.
+    data_len: u32,
+    /// The first error of `write`, reported by `flush`.
+    error: Option<io::Error>,
+}
+
+impl WavSink<BufWriter<File>> {
+    /// Creates the file at `path`, sampled at [`WAV_SAMPLE_RATE`].
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<WavSink<BufWriter<File>>> {
+        WavSink::new(BufWriter::new(File::create(path)?), WAV_SAMPLE_RATE)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    /// Writes the header to `writer`, the samples follow it.
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<WavSink<W>> {
+        write_header(&mut writer, sample_rate, 0)?;
+
+        Ok(WavSink {
+            writer: Some(writer),
+            sample_rate,
+            data_len: 0,
+            error: None,
+        })
+    }
+
+    /// Finishes the file and gives the writer back.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+
+        Ok(self.writer.take().expect("the writer is only taken here"))
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_all(&bytes)?;
+        }
+        self.data_len += bytes.len() as u32;
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Err(e) = self.write_samples(samples) {
+            self.error = Some(e);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        let writer = match self.writer.as_mut() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(0))?;
+        write_header(writer, self.sample_rate, self.data_len)?;
+        writer.seek(SeekFrom::Start(end))?;
+
+        writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W, sample_rate: u32, data_len: u32) -> io::Result<()> {
+    let channels: u16 = 1;
+    let bits_per_sample: u16 = 16;
+    let block_align = channels * bits_per_sample / 8;
+
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(HEADER_SIZE - 8 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // PCM
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+
+    writer.write_all(&header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Chip8;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    /// A cursor the test keeps reading after the machine took the sink.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Cursor<Vec<u8>>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Shared {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.borrow_mut().seek(pos)
+        }
+    }
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([data[at], data[at + 1]])
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    #[test]
+    fn records_the_buzzer() {
+        let shared = Shared::default();
+        let mut chip8 = Chip8::new();
+        // LD V0, #$0A; LD ST, V0; JP $204
+        chip8
+            .load_rom(&[0x60, 0x0A, 0xF0, 0x18, 0x12, 0x04])
+            .unwrap();
+        chip8.set_audio_sink(Box::new(WavSink::new(shared.clone(), 48_000).unwrap()));
+
+        // The timer runs out after 10 frames, the last 5 are silent.
+        for _ in 0..15 {
+            chip8.run_frame().unwrap();
+        }
+        let mut sink = chip8.remove_audio_sink().unwrap();
+        sink.flush().unwrap();
+
+        let data = shared.0.borrow().get_ref().clone();
+        let frame = 48_000 / 60 * 2;
+        let data_len = 15 * frame as u32;
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4), HEADER_SIZE - 8 + data_len);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(&data, 20), 1);
+        assert_eq!(u16_at(&data, 22), 1);
+        assert_eq!(u32_at(&data, 24), 48_000);
+        assert_eq!(u32_at(&data, 28), 48_000 * 2);
+        assert_eq!(u16_at(&data, 34), 16);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), data_len);
+        assert_eq!(data.len(), (HEADER_SIZE + data_len) as usize);
+
+        let samples = &data[HEADER_SIZE as usize..];
+        assert!(samples[..frame].iter().any(|b| *b != 0));
+        assert!(samples[samples.len() - frame..].iter().all(|b| *b == 0));
+    }
+}